@@ -3,6 +3,7 @@
 global syscall_entry
 
 extern syscalls
+extern syscall_return
 
 section .text
 bits 64
@@ -45,7 +46,7 @@ syscall_entry:
 	shl rax, 32		; cant use and because it messes things up
 	shr rax, 32
 
-	cmp rax, 41		; make sure it is a valid syscall
+	cmp rax, 60		; make sure it is a valid syscall
 	jg .invalid_syscall
 
 	mov rdi, rsp
@@ -55,6 +56,9 @@ syscall_entry:
 	mov rax, [r10 + rax * 8]
 	call rax		; stack is already 16 byte aligned
 
+	lea rdi, [rsp + 8]	; divert into asynchronous event handlers if any events were recieved
+	call syscall_return
+
 	add rsp, 8		; put stack pointer to right place
 	pop rax
 
@@ -79,17 +83,9 @@ syscall_entry:
 	pop rcx			; restore return rip
 
 	cli
-	swapgs
-
-	mov r10, [gs:gs_data_ptr.ptr]		; get gs ptr
-	mov [gs:gs_data_ptr.temp], rcx		; return rip in temporary location
-	mov rcx, [r10 + gs_data.call_save_rsp]	; get save rsp and put in rcx
 
-
-	pop r10			; read old rsp
+	pop r10			; read old rsp, which is changed when returning into an asynchronous event handler
 	pop r11			; restore flags
-	mov rsp, rcx	; restore save rsp
+	mov rsp, r10	; restore old rsp
 
-	mov rcx, [gs:gs_data_ptr.temp]	; load return rip from temporary location
-	swapgs
 	o64 sysret
@@ -1,46 +1,67 @@
 use crate::uses::*;
 use core::sync::atomic::{AtomicUsize, Ordering};
 use core::ops::DerefMut;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use alloc::collections::BTreeMap;
 use bitflags::bitflags;
+use spin::Mutex;
 use crate::util::FutexGuard;
 use crate::make_id_type;
 use crate::mem::{PAGE_SIZE, VirtRange};
-use crate::sched::proc_c;
+use crate::sched::{thread_c, Pid, Process};
 use crate::mem::virt_alloc::{VirtLayout, AllocType};
 use crate::util::Futex;
 
 pub mod sys;
 
+// bits 0-3 are the CapPriv privelidges from the spec, and bit 4 is the cap_weak flag
 bitflags! {
 	pub struct CapFlags: usize {
 		const READ = 1;
-		const WRITE = 1 << 1;
+		const PROD = 1 << 1;
+		const WRITE = 1 << 2;
+		// allows making a strong capability from a weak capability
+		const UPGRADE = 1 << 3;
+		// the capability does not keep the object alive
+		const WEAK = 1 << 4;
 	}
 }
 
+// numbered the same as cap_type in the spec, types that are not in the spec come after the spec's types
+// types from the spec that the kernel does not have objects for yet are left out, so their ids are rejected as invalid
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CapObjectType {
-	Channel = 0,
-	Futex = 1,
-	SMem = 2,
-	Key = 3,
-	Mmio = 4,
+	Process = 0,
+	// Memory in the spec
+	SMem = 1,
+	Event = 2,
+	Channel = 3,
+	Key = 4,
 	Interrupt = 5,
 	Port = 6,
+	Spawner = 7,
+	Allocator = 8,
+	IntAllocator = 11,
+	Futex = 13,
+	// TODO: make this a Memory object once mmio is implemented
+	Mmio = 14,
 }
 
 impl CapObjectType {
 	fn from(n: usize) -> Option<CapObjectType> {
 		Some(match n {
-			0 => Self::Channel,
-			1 => Self::Futex,
-			2 => Self::SMem,
-			3 => Self::Key,
-			4 => Self::Mmio,
+			0 => Self::Process,
+			1 => Self::SMem,
+			2 => Self::Event,
+			3 => Self::Channel,
+			4 => Self::Key,
 			5 => Self::Interrupt,
 			6 => Self::Port,
+			7 => Self::Spawner,
+			8 => Self::Allocator,
+			11 => Self::IntAllocator,
+			13 => Self::Futex,
+			14 => Self::Mmio,
 			_ => return None,
 		})
 	}
@@ -56,6 +77,11 @@ pub trait CapObject {
 	fn cap_object_type() -> CapObjectType;
 	fn inc_ref(&self);
 	fn dec_ref(&self);
+
+	// type specific information returned by cap_info
+	fn info(&self) -> [usize; 2] {
+		[0, 0]
+	}
 }
 
 pub trait Map: CapObject {
@@ -63,11 +89,14 @@ pub trait Map: CapObject {
 
 	fn virt_layout(&self, flags: CapFlags) -> VirtLayout;
 	fn alloc_type(&self) -> AllocType;
-	fn cap_map_data(&self, id: CapId) -> (Option<VirtRange>, Self::Lock<'_>);
-	fn set_cap_map_data(&self, id: CapId, data: Option<VirtRange>, lock: Self::Lock<'_>);
+	// capability ids are only unique within a process, so map data is also keyed by the process id
+	fn cap_map_data(&self, pid: Pid, id: CapId) -> (Option<VirtRange>, Self::Lock<'_>);
+	fn set_cap_map_data(&self, pid: Pid, id: CapId, data: Option<VirtRange>, lock: Self::Lock<'_>);
 
-	fn map(&self, id: CapId, at_addr: Option<usize>) -> Result<VirtRange, SysErr> {
-		let (layout, lock) = self.cap_map_data(id);
+	// maps the memory referenced by capability id into the address space of process
+	// force allows mapping at_addr inside of reserved memory
+	fn map(&self, process: &Process, id: CapId, at_addr: Option<usize>, force: bool) -> Result<VirtRange, SysErr> {
+		let (layout, lock) = self.cap_map_data(process.pid(), id);
 
 		match layout {
 			Some(_) => Err(SysErr::InvlOp),
@@ -81,29 +110,33 @@ pub trait Map: CapObject {
 					let vrange = VirtRange::new(vaddr, vlayout.size());
 
 					unsafe {
-						proc_c().addr_space.map_at(vlayout, vrange)?
+						if force {
+							process.addr_space.map_at_force(vlayout, vrange)?
+						} else {
+							process.addr_space.map_at(vlayout, vrange)?
+						}
 					}
 				} else {
 					unsafe {
-						proc_c().addr_space.map(vlayout)?
+						process.addr_space.map(vlayout)?
 					}
 				};
 
-				self.set_cap_map_data(id, Some(virt_range), lock);
+				self.set_cap_map_data(process.pid(), id, Some(virt_range), lock);
 				Ok(virt_range)
 			},
 		}
 	}
 
-	fn unmap(&self, id: CapId) -> Result<(), SysErr> {
-		let (layout, lock) = self.cap_map_data(id);
+	fn unmap(&self, process: &Process, id: CapId) -> Result<(), SysErr> {
+		let (layout, lock) = self.cap_map_data(process.pid(), id);
 
 		match layout {
 			Some(layout) => {
 				unsafe {
-					proc_c().addr_space.unmap(layout, self.alloc_type()).unwrap();
+					process.addr_space.unmap(layout, self.alloc_type()).unwrap();
 				}
-				self.set_cap_map_data(id, None, lock);
+				self.set_cap_map_data(process.pid(), id, None, lock);
 				Ok(())
 			},
 			None => Err(SysErr::InvlOp),
@@ -118,36 +151,179 @@ impl CapId {
 		CapFlags::from_bits_truncate(self.into())
 	}
 
-	fn cap_object_type(self) -> CapObjectType {
-		CapObjectType::from(get_bits(self.into(), 2..5)).unwrap()
+	// returns None if the type bits do not represent a valid type, which can happen for ids passed in by userspace
+	pub fn cap_object_type(self) -> Option<CapObjectType> {
+		CapObjectType::from(get_bits(self.into(), 5..9))
+	}
+
+	pub fn is_weak(self) -> bool {
+		self.flags().contains(CapFlags::WEAK)
+	}
+}
+
+// node in the capability derivation tree
+// capabilities made from another capability with an explicit clone or move are children of that capability's node,
+// and copies made by the kernel, like capabilities sent in messages, share the node of the capability they were copied from
+// parents only hold weak references to their children, so a node is freed once no capability uses it,
+// and its children are then given to its parent, so they can still be revoked by their remaining ancestors
+#[derive(Debug)]
+pub struct CapNode {
+	parent: Mutex<Weak<CapNode>>,
+	children: Mutex<Vec<Weak<CapNode>>>,
+	// process and id of every capability in a capability map that uses this node
+	caps: Mutex<Vec<(Pid, CapId)>>,
+}
+
+impl CapNode {
+	fn new() -> Arc<Self> {
+		Arc::new(CapNode {
+			parent: Mutex::new(Weak::new()),
+			children: Mutex::new(Vec::new()),
+			caps: Mutex::new(Vec::new()),
+		})
+	}
+
+	fn new_child(parent: &Arc<CapNode>) -> Arc<Self> {
+		let node = Arc::new(CapNode {
+			parent: Mutex::new(Arc::downgrade(parent)),
+			children: Mutex::new(Vec::new()),
+			caps: Mutex::new(Vec::new()),
+		});
+
+		let mut children = parent.children.lock();
+		// forget children that have been freed, so the list doesn't keep growing
+		children.retain(|child| child.strong_count() != 0);
+		children.push(Arc::downgrade(&node));
+		drop(children);
+
+		node
+	}
+
+	// called by capability maps when a capability using this node is inserted or removed
+	pub fn add_cap(&self, pid: Pid, id: CapId) {
+		self.caps.lock().push((pid, id));
+	}
+
+	pub fn remove_cap(&self, pid: Pid, id: CapId) {
+		self.caps.lock().retain(|cap| *cap != (pid, id));
+	}
+
+	fn live_children(&self) -> Vec<Arc<CapNode>> {
+		self.children.lock().iter().filter_map(Weak::upgrade).collect()
+	}
+
+	// returns the process and id of every capability derived from this node
+	pub fn descendant_caps(&self) -> Vec<(Pid, CapId)> {
+		let mut out = Vec::new();
+		let mut nodes = self.live_children();
+
+		while let Some(node) = nodes.pop() {
+			out.extend_from_slice(&node.caps.lock());
+			nodes.append(&mut node.live_children());
+		}
+
+		out
+	}
+}
+
+impl Drop for CapNode {
+	fn drop(&mut self) {
+		let parent = self.parent.lock().upgrade();
+		let children: Vec<_> = self.children.lock().drain(..).collect();
+
+		// children are upgraded before locking the parent, and dropped after,
+		// because dropping the last reference to a child would lock the parent's children
+		let live: Vec<_> = children.iter().filter_map(Weak::upgrade).collect();
+		for child in live.iter() {
+			*child.parent.lock() = parent.as_ref().map_or_else(Weak::new, Arc::downgrade);
+		}
+
+		if let Some(parent) = &parent {
+			let mut parent_children = parent.children.lock();
+			parent_children.retain(|child| child.strong_count() != 0);
+			parent_children.extend(live.iter().map(Arc::downgrade));
+		}
 	}
 }
 
+#[derive(Debug)]
+enum CapRef<T> {
+	Strong(Arc<T>),
+	Weak(Weak<T>),
+}
+
+// only strong capabilities count as references to the object for inc_ref and dec_ref
 #[derive(Debug)]
 pub struct Capability<T: CapObject> {
-	object: Arc<T>,
+	object: CapRef<T>,
 	flags: CapFlags,
 	id: CapId,
+	node: Arc<CapNode>,
+	// word given to the reciever of every message sent with this capability, 0 if the capability has no badge
+	// only channel capabilities can have a badge
+	badge: usize,
 }
 
 impl<T: CapObject> Capability<T> {
+	// makes a strong capability, the weak flag is ignored
 	pub fn new(object: Arc<T>, flags: CapFlags) -> Self {
 		object.inc_ref();
 		Capability {
-			object,
-			flags,
+			object: CapRef::Strong(object),
+			flags: flags & !CapFlags::WEAK,
 			id: CapId::from(0),
+			node: CapNode::new(),
+			badge: 0,
 		}
 	}
 
-	pub fn and_from_flags(cap: &Self, flags: CapFlags) -> Self {
-		let mut out = cap.clone();
-		out.flags &= flags;
-		out
+	pub fn new_weak(object: &Arc<T>, flags: CapFlags) -> Self {
+		Capability {
+			object: CapRef::Weak(Arc::downgrade(object)),
+			flags: flags | CapFlags::WEAK,
+			id: CapId::from(0),
+			node: CapNode::new(),
+			badge: 0,
+		}
+	}
+
+	// makes a new capability referencing the same object, with the privelidges in flags anded with this capability's privelidges
+	// the new capability is weak if flags has the weak flag set, and it is a child of this capability in the derivation tree
+	// making a strong capability from a weak capability requires the upgrade privelidge
+	pub fn clone_with(&self, flags: CapFlags) -> Result<Self, SysErr> {
+		let privs = self.flags & flags & !CapFlags::WEAK;
+
+		if flags.contains(CapFlags::WEAK) {
+			let object = match &self.object {
+				CapRef::Strong(object) => Arc::downgrade(object),
+				CapRef::Weak(object) => object.clone(),
+			};
+
+			Ok(Capability {
+				object: CapRef::Weak(object),
+				flags: privs | CapFlags::WEAK,
+				id: CapId::from(0),
+				node: CapNode::new_child(&self.node),
+				badge: self.badge,
+			})
+		} else {
+			if self.is_weak() && !self.flags.contains(CapFlags::UPGRADE) {
+				return Err(SysErr::InvlPerm);
+			}
+
+			let mut cap = Capability::new(self.upgrade()?, privs);
+			cap.node = CapNode::new_child(&self.node);
+			cap.badge = self.badge;
+			Ok(cap)
+		}
 	}
 
+	// panics if the capability is weak, so only use this on capabilities the kernel made as strong capabilities
 	pub fn object(&self) -> &T {
-		&self.object
+		match &self.object {
+			CapRef::Strong(object) => object,
+			CapRef::Weak(_) => panic!("called object on weak capability"),
+		}
 	}
 
 	pub fn flags(&self) -> CapFlags {
@@ -158,49 +334,124 @@ impl<T: CapObject> Capability<T> {
 		self.id
 	}
 
+	pub fn is_weak(&self) -> bool {
+		matches!(self.object, CapRef::Weak(_))
+	}
+
+	pub fn node(&self) -> &Arc<CapNode> {
+		&self.node
+	}
+
+	pub fn badge(&self) -> usize {
+		self.badge
+	}
+
+	// badges can't be changed once they are set, so this returns InvlOp if the capability already has a badge
+	pub fn set_badge(&mut self, badge: usize) -> Result<(), SysErr> {
+		if self.badge != 0 {
+			Err(SysErr::InvlOp)
+		} else {
+			self.badge = badge;
+			Ok(())
+		}
+	}
+
+	pub fn is_alive(&self) -> bool {
+		match &self.object {
+			CapRef::Strong(_) => true,
+			CapRef::Weak(object) => object.strong_count() != 0,
+		}
+	}
+
+	// returns the type specific information of the object, or all zeros if the object is no longer alive
+	// unlike upgrade, this does not record dead weak capabilities
+	pub fn info(&self) -> [usize; 2] {
+		match &self.object {
+			CapRef::Strong(object) => object.info(),
+			CapRef::Weak(object) => object.upgrade().map_or([0, 0], |object| object.info()),
+		}
+	}
+
+	// panics if the capability is weak, so only use this on capabilities the kernel made as strong capabilities
 	pub fn arc_clone(&self) -> Arc<T> {
-		self.object.clone()
+		match &self.object {
+			CapRef::Strong(object) => object.clone(),
+			CapRef::Weak(_) => panic!("called arc_clone on weak capability"),
+		}
+	}
+
+	// returns InvlWeak if the capability is weak and the object is no longer alive
+	// the capability is recorded in the current thread, so it can be destroyed if the syscall used weak_auto_destroy
+	pub fn upgrade(&self) -> Result<Arc<T>, SysErr> {
+		match &self.object {
+			CapRef::Strong(object) => Ok(object.clone()),
+			CapRef::Weak(object) => object.upgrade().ok_or_else(|| {
+				thread_c().push_dead_weak(self.id);
+				SysErr::InvlWeak
+			}),
+		}
 	}
 
 	pub fn set_base_id(&mut self, id: usize) -> CapId {
-		assert!(id < (1 << 59));
-		self.id = CapId::from((id << 5) | (T::cap_object_type().as_usize() << 2) | (self.flags.bits()));
+		assert!(id < (1 << 55));
+		self.id = CapId::from((id << 9) | (T::cap_object_type().as_usize() << 5) | (self.flags.bits()));
 		self.id
 	}
 }
 
 impl<T: CapObject> Clone for Capability<T> {
 	fn clone(&self) -> Self {
-		self.object.inc_ref();
+		let object = match &self.object {
+			CapRef::Strong(object) => {
+				object.inc_ref();
+				CapRef::Strong(object.clone())
+			},
+			CapRef::Weak(object) => CapRef::Weak(object.clone()),
+		};
+
+		// copies share the original's node, so revoking an ancestor also revokes copies sent to other processes
 		Capability {
-			object: self.object.clone(),
+			object,
 			flags: self.flags,
 			id: CapId::from(0),
+			node: self.node.clone(),
+			badge: self.badge,
 		}
 	}
 }
 
 impl<T: CapObject> Drop for Capability<T> {
 	fn drop(&mut self) {
-		self.object.dec_ref();
+		if let CapRef::Strong(object) = &self.object {
+			object.dec_ref();
+		}
 	}
 }
 
 // Syscalls on capabilities go in here
 pub trait CapSys {
 	fn destroy(&self, id: CapId) -> bool;
-	fn clone_cap(&self, id: CapId, flags: CapFlags) -> Option<CapId>;
+	fn clone_cap(&self, id: CapId, flags: CapFlags) -> Result<CapId, SysErr>;
+	// returns InvlArgs if the capability is not weak, and InvlWeak if the object is not alive
+	fn weak_is_alive(&self, id: CapId) -> SysErr;
+	// returns the flags of the capability and the type specific information of its object
+	fn cap_info(&self, id: CapId) -> Result<(CapFlags, [usize; 2]), SysErr>;
+	// returns the capability's node in the derivation tree
+	fn cap_node(&self, id: CapId) -> Result<Arc<CapNode>, SysErr>;
 }
 
+// pid is the process that owns the capabilities, which is recorded in their derivation tree nodes
 #[derive(Debug)]
 pub struct CapMap<T: CapObject> {
+	pid: Pid,
 	data: Futex<BTreeMap<CapId, Capability<T>>>,
 	next_id: AtomicUsize,
 }
 
 impl<T: CapObject> CapMap<T> {
-	pub fn new() -> Self {
+	pub fn new(pid: Pid) -> Self {
 		CapMap {
+			pid,
 			data: Futex::new(BTreeMap::new()),
 			next_id: AtomicUsize::new(0),
 		}
@@ -209,12 +460,17 @@ impl<T: CapObject> CapMap<T> {
 	pub fn insert(&self, mut cap: Capability<T>) -> CapId {
 		let id = self.next_id.fetch_add(1, Ordering::Relaxed);
 		let id = cap.set_base_id(id);
-		self.data.lock().insert(id, cap);
+		// the node is updated while the map is locked, so revoke can't miss the capability
+		let mut data = self.data.lock();
+		cap.node().add_cap(self.pid, id);
+		data.insert(id, cap);
 		id
 	}
 
 	pub fn remove(&self, id: CapId) -> Option<Capability<T>> {
-		self.data.lock().remove(&id)
+		let cap = self.data.lock().remove(&id)?;
+		cap.node().remove_cap(self.pid, id);
+		Some(cap)
 	}
 
 	pub fn call<F, U>(&self, id: CapId, f: F) -> Option<U>
@@ -222,28 +478,71 @@ impl<T: CapObject> CapMap<T> {
 	{
 		let lock = self.data.lock();
 		let cap = lock.get(&id)?;
-		Some(f(&cap.object, cap.flags))
+		Some(f(&*cap.upgrade().ok()?, cap.flags))
 	}
 
 	pub fn clone_from(&self, id: CapId) -> Option<Capability<T>> {
 		let lock = self.data.lock();
 		Some(lock.get(&id)?.clone())
 	}
+
+	// see Capability::clone_with
+	pub fn clone_with(&self, id: CapId, flags: CapFlags) -> Result<Capability<T>, SysErr> {
+		let lock = self.data.lock();
+		lock.get(&id).ok_or(SysErr::InvlId)?.clone_with(flags)
+	}
+
+	// clones the capability into other using Capability::clone_with, and returns the id of the new capability
+	pub fn clone_to(&self, id: CapId, other: &CapMap<T>, flags: CapFlags) -> Result<CapId, SysErr> {
+		Ok(other.insert(self.clone_with(id, flags)?))
+	}
+
+	// like clone_to, but the new capability has the badge, see Capability::set_badge
+	pub fn mint_to(&self, id: CapId, other: &CapMap<T>, flags: CapFlags, badge: usize) -> Result<CapId, SysErr> {
+		let mut cap = self.clone_with(id, flags)?;
+		cap.set_badge(badge)?;
+		Ok(other.insert(cap))
+	}
+
+	// like clone_to, but the original capability is destroyed
+	// the original capability is kept if the new capability can't be made
+	pub fn move_to(&self, id: CapId, other: &CapMap<T>, flags: CapFlags) -> Result<CapId, SysErr> {
+		let cap = self.clone_with(id, flags)?;
+		self.remove(id).ok_or(SysErr::InvlId)?;
+		Ok(other.insert(cap))
+	}
+
+	// returns the object referenced by the capability if the capability has at least the permissions in flags
+	pub fn fetch(&self, id: CapId, flags: CapFlags) -> Result<Arc<T>, SysErr> {
+		Ok(self.fetch_badged(id, flags)?.0)
+	}
+
+	// like fetch, but also returns the capability's badge
+	pub fn fetch_badged(&self, id: CapId, flags: CapFlags) -> Result<(Arc<T>, usize), SysErr> {
+		let lock = self.data.lock();
+		let cap = lock.get(&id).ok_or(SysErr::InvlId)?;
+		if cap.flags().contains(flags) {
+			Ok((cap.upgrade()?, cap.badge()))
+		} else {
+			Err(SysErr::InvlPerm)
+		}
+	}
 }
 
+// process must be the process that owns this CapMap
 impl<T: Map> CapMap<T> {
-	fn map(&self, id: CapId, at_addr: Option<usize>) -> Result<VirtRange, SysErr> {
+	pub fn map(&self, process: &Process, id: CapId, at_addr: Option<usize>, force: bool) -> Result<VirtRange, SysErr> {
 		let lock = self.data.lock();
 		match lock.get(&id) {
-			Some(cap) => cap.object().map(id, at_addr),
+			Some(cap) => cap.upgrade()?.map(process, id, at_addr, force),
 			None => Err(SysErr::InvlId),
 		}
 	}
 
-	fn unmap(&self, id: CapId) -> Result<(), SysErr> {
+	pub fn unmap(&self, process: &Process, id: CapId) -> Result<(), SysErr> {
 		let lock = self.data.lock();
 		match lock.get(&id) {
-			Some(cap) => cap.object().unmap(id),
+			Some(cap) => cap.upgrade()?.unmap(process, id),
 			None => Err(SysErr::InvlId),
 		}
 	}
@@ -254,11 +553,40 @@ impl<T: CapObject> CapSys for CapMap<T> {
 		self.remove(id).is_some()
 	}
 
-	fn clone_cap(&self, id: CapId, flags: CapFlags) -> Option<CapId> {
+	fn clone_cap(&self, id: CapId, flags: CapFlags) -> Result<CapId, SysErr> {
 		let lock = self.data.lock();
-		let cap = lock.get(&id)?;
-		let new_cap = Capability::and_from_flags(cap, flags);
+		let cap = lock.get(&id).ok_or(SysErr::InvlId)?;
+		let new_cap = cap.clone_with(flags)?;
 		drop(lock);
-		Some(self.insert(new_cap))
+		Ok(self.insert(new_cap))
+	}
+
+	fn weak_is_alive(&self, id: CapId) -> SysErr {
+		match self.data.lock().get(&id) {
+			Some(cap) if !cap.is_weak() => SysErr::InvlArgs,
+			Some(cap) if cap.is_alive() => SysErr::Ok,
+			Some(_) => SysErr::InvlWeak,
+			None => SysErr::InvlId,
+		}
+	}
+
+	fn cap_info(&self, id: CapId) -> Result<(CapFlags, [usize; 2]), SysErr> {
+		let lock = self.data.lock();
+		let cap = lock.get(&id).ok_or(SysErr::InvlId)?;
+		Ok((cap.flags(), cap.info()))
+	}
+
+	fn cap_node(&self, id: CapId) -> Result<Arc<CapNode>, SysErr> {
+		let lock = self.data.lock();
+		Ok(lock.get(&id).ok_or(SysErr::InvlId)?.node().clone())
+	}
+}
+
+impl<T: CapObject> Drop for CapMap<T> {
+	// the capabilities are freed with the map, so they are no longer in their nodes
+	fn drop(&mut self) {
+		for (id, cap) in self.data.lock().iter() {
+			cap.node().remove_cap(self.pid, *id);
+		}
 	}
 }
@@ -1,32 +1,180 @@
+use sys_consts::options::MapOptions;
+
 use crate::uses::*;
 use crate::sysret;
 use crate::syscall::SyscallVals;
+use crate::syscall::udata::UserArray;
 use crate::mem::PAGE_SIZE;
-use crate::sched::proc_c;
+use crate::sched::{proc_c, proc_get, Process};
 use super::{CapId, CapFlags, CapObjectType};
 
 pub extern "C" fn cap_destroy(vals: &mut SyscallVals) {
-	let id = CapId::from(vals.a1);
+	let id = CapId::from(vals.a2);
 
-	let err = if proc_c().get_capmap(id.cap_object_type()).destroy(id) {
-		SysErr::Ok
-	} else {
-		SysErr::InvlId
+	let process = match proc_c().processes().fetch(CapId::from(vals.a1), CapFlags::WRITE) {
+		Ok(process) => process,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	let err = match id.cap_object_type() {
+		Some(CapObjectType::Mmio | CapObjectType::Port) | None => SysErr::InvlId,
+		Some(typ) if process.get_capmap(typ).destroy(id) => SysErr::Ok,
+		Some(_) => SysErr::InvlId,
 	};
 
 	sysret!(vals, err.num());
 }
 
+// if a3 is not 0, it is the badge of the new capability, which must be a channel capability that has no badge yet
 pub extern "C" fn cap_clone(vals: &mut SyscallVals) {
-	let id = CapId::from(vals.a1);
+	let id = CapId::from(vals.a2);
+	let flags = CapFlags::from_bits_truncate(vals.options as usize);
+	let badge = vals.a3;
+
+	let process = match proc_c().processes().fetch(CapId::from(vals.a1), CapFlags::WRITE) {
+		Ok(process) => process,
+		Err(err) => sysret!(vals, err.num(), 0),
+	};
+
+	let out = if badge == 0 {
+		proc_c().clone_cap_with(id, &process, flags)
+	} else if id.cap_object_type() == Some(CapObjectType::Channel) {
+		proc_c().channels().mint_to(id, process.channels(), flags, badge)
+	} else {
+		Err(SysErr::InvlArgs)
+	};
+
+	match out {
+		Ok(id) => sysret!(vals, SysErr::Ok.num(), id.into()),
+		Err(err) => sysret!(vals, err.num(), 0),
+	}
+}
+
+pub extern "C" fn cap_move(vals: &mut SyscallVals) {
+	let flags = CapFlags::from_bits_truncate(vals.options as usize);
+
+	let process = match proc_c().processes().fetch(CapId::from(vals.a1), CapFlags::WRITE) {
+		Ok(process) => process,
+		Err(err) => sysret!(vals, err.num(), 0),
+	};
+
+	match proc_c().move_cap_with(CapId::from(vals.a2), &process, flags) {
+		Ok(id) => sysret!(vals, SysErr::Ok.num(), id.into()),
+		Err(err) => sysret!(vals, err.num(), 0),
+	}
+}
+
+// runs op on every capability id in the array at a2 with length a3, with the process in a1
+// the error of each operation is written to the array at a4, which has the same length,
+// and if ret_ids is set, each id in the array at a2 is replaced with the id returned by op, or 0 if op failed
+// returns the number of operations that succeeded
+fn cap_batch<F>(vals: &mut SyscallVals, ret_ids: bool, mut op: F)
+	where F: FnMut(CapId, &Process) -> Result<CapId, SysErr>
+{
+	let process = match proc_c().processes().fetch(CapId::from(vals.a1), CapFlags::WRITE) {
+		Ok(process) => process,
+		Err(err) => sysret!(vals, err.num(), 0),
+	};
+
+	let ids = UserArray::from_parts(vals.a2 as *const usize, vals.a3);
+	let errs = UserArray::from_parts(vals.a4 as *const usize, vals.a3);
+
+	let mut id_vec = match ids.try_fetch() {
+		Some(id_vec) => id_vec,
+		None => sysret!(vals, SysErr::InvlPtr.num(), 0),
+	};
+
+	// check the arrays that are written to are writable before doing anything, so the results can't be lost
+	if !errs.writable() || (ret_ids && !ids.writable()) {
+		sysret!(vals, SysErr::InvlPtr.num(), 0);
+	}
+
+	let mut err_vec = Vec::with_capacity(id_vec.len());
+	let mut count = 0;
+	for id in id_vec.iter_mut() {
+		let err = match op(CapId::from(*id), &process) {
+			Ok(new_id) => {
+				*id = new_id.into();
+				count += 1;
+				SysErr::Ok
+			},
+			Err(err) => {
+				*id = 0;
+				err
+			},
+		};
+		err_vec.push(err.num());
+	}
+
+	// the arrays could have been unmapped by another thread since they were checked
+	if errs.try_write(&err_vec).is_none() || (ret_ids && ids.try_write(&id_vec).is_none()) {
+		sysret!(vals, SysErr::InvlPtr.num(), count);
+	}
+
+	sysret!(vals, SysErr::Ok.num(), count);
+}
+
+pub extern "C" fn cap_destroy_many(vals: &mut SyscallVals) {
+	cap_batch(vals, false, |id, process| match id.cap_object_type() {
+		Some(CapObjectType::Mmio | CapObjectType::Port) | None => Err(SysErr::InvlId),
+		Some(typ) if process.get_capmap(typ).destroy(id) => Ok(id),
+		Some(_) => Err(SysErr::InvlId),
+	});
+}
+
+pub extern "C" fn cap_clone_many(vals: &mut SyscallVals) {
+	let flags = CapFlags::from_bits_truncate(vals.options as usize);
+	let current = proc_c();
+	cap_batch(vals, true, |id, process| current.clone_cap_with(id, process, flags));
+}
+
+pub extern "C" fn cap_move_many(vals: &mut SyscallVals) {
 	let flags = CapFlags::from_bits_truncate(vals.options as usize);
+	let current = proc_c();
+	cap_batch(vals, true, |id, process| current.move_cap_with(id, process, flags));
+}
+
+// destroys every capability derived from the capability in a1 in all processes, but not the capability itself
+pub extern "C" fn cap_revoke(vals: &mut SyscallVals) {
+	let id = CapId::from(vals.a1);
+
+	let typ = match id.cap_object_type() {
+		Some(CapObjectType::Mmio | CapObjectType::Port) | None => sysret!(vals, SysErr::InvlId.num()),
+		Some(typ) => typ,
+	};
+
+	let node = match proc_c().get_capmap(typ).cap_node(id) {
+		Ok(node) => node,
+		Err(err) => sysret!(vals, err.num()),
+	};
 
-	match proc_c().get_capmap(id.cap_object_type()).clone_cap(id, flags) {
-		Some(id) => sysret!(vals, SysErr::Ok.num(), id.into()),
-		None => sysret!(vals, SysErr::InvlId.num(), 0),
+	// capabilities derived from another capability always have the same type
+	for (pid, id) in node.descendant_caps() {
+		if let Some(process) = proc_get(pid) {
+			process.revoke_cap(typ, id);
+		}
 	}
+
+	sysret!(vals, SysErr::Ok.num());
 }
 
+pub extern "C" fn weak_is_alive(vals: &mut SyscallVals) {
+	let id = CapId::from(vals.a2);
+
+	let process = match proc_c().processes().fetch(CapId::from(vals.a1), CapFlags::WRITE) {
+		Ok(process) => process,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	let err = match id.cap_object_type() {
+		Some(CapObjectType::Mmio | CapObjectType::Port) | None => SysErr::InvlId,
+		Some(typ) => process.get_capmap(typ).weak_is_alive(id),
+	};
+
+	sysret!(vals, err.num());
+}
+
+// map_force allows mapping memory inside of reserved memory, and is ignored if no address is passed in
 pub extern "C" fn cap_map(vals: &mut SyscallVals) {
 	let id = CapId::from(vals.a1);
 	let at_addr = if vals.a2 == 0 {
@@ -34,10 +182,14 @@ pub extern "C" fn cap_map(vals: &mut SyscallVals) {
 	} else {
 		Some(vals.a2)
 	};
+	let force = MapOptions::from_bits_truncate(vals.options).contains(MapOptions::FORCE);
 
 	let out = match id.cap_object_type() {
-		CapObjectType::SMem => proc_c().smem().map(id, at_addr),
-		CapObjectType::Mmio => todo!(),
+		Some(CapObjectType::SMem) => {
+			let process = proc_c();
+			process.smem().map(&process, id, at_addr, force)
+		},
+		Some(CapObjectType::Mmio) => todo!(),
 		_ => Err(SysErr::InvlId),
 	};
 
@@ -51,8 +203,11 @@ pub extern "C" fn cap_unmap(vals: &mut SyscallVals) {
 	let id = CapId::from(vals.a1);
 
 	let out = match id.cap_object_type() {
-		CapObjectType::SMem => proc_c().smem().unmap(id),
-		CapObjectType::Mmio => todo!(),
+		Some(CapObjectType::SMem) => {
+			let process = proc_c();
+			process.smem().unmap(&process, id)
+		},
+		Some(CapObjectType::Mmio) => todo!(),
 		_ => Err(SysErr::InvlId),
 	};
 
@@ -62,6 +217,24 @@ pub extern "C" fn cap_unmap(vals: &mut SyscallVals) {
 	}
 }
 
+// returns the object type, flags, and 2 words of type specific information:
+// memory: size in pages
+// channel: msg_size, max_caps
+// key: key id
+// interrupt: interrupt vector
+// allocator: used pages, max pages
+// the type specific information is 0 if there is none, or if the capability is weak and its object is no longer alive
 pub extern "C" fn cap_info(vals: &mut SyscallVals) {
-	todo!();
+	let id = CapId::from(vals.a1);
+
+	let out = match id.cap_object_type() {
+		// TODO: return the port number once ports are implemented
+		Some(CapObjectType::Mmio | CapObjectType::Port) | None => Err(SysErr::InvlId),
+		Some(typ) => proc_c().get_capmap(typ).cap_info(id).map(|info| (typ, info)),
+	};
+
+	match out {
+		Ok((typ, (flags, data))) => sysret!(vals, SysErr::Ok.num(), typ.as_usize(), flags.bits(), data[0], data[1]),
+		Err(err) => sysret!(vals, err.num(), 0, 0, 0, 0),
+	}
 }
@@ -0,0 +1,281 @@
+use core::cmp::min;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+use spin::Mutex;
+
+use crate::uses::*;
+use crate::cap::{CapFlags, CapId, CapObject, CapObjectType, Capability};
+use crate::mem::allocator::Allocator;
+use crate::sched::{block, thread_c, thread_get, tlist, ThreadState, Tuid};
+
+pub mod sys;
+
+crate::make_id_type!(Evid);
+
+static NEXT_EVID: AtomicUsize = AtomicUsize::new(0);
+
+// maximum number of arguments an event can take
+pub const MAX_ARGS: usize = 8;
+
+// maximum number of events that can be sent with 1 event_send
+pub const MAX_SEND_COUNT: usize = 256;
+
+// pages charged to the allocator of an event, for the kernel data used by the event
+const EVENT_PAGES: usize = 1;
+
+pub type EventArgs = [usize; MAX_ARGS];
+
+// an event that has been sent to a thread that was asynchronously listening, and has not yet been handled
+#[derive(Debug, Clone, Copy)]
+pub struct AsyncEvent {
+	pub handler: usize,
+	// cid of the event in the listening thread's process
+	pub cid: CapId,
+	pub args: EventArgs,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AsyncListener {
+	tuid: Tuid,
+	cid: CapId,
+	handler: usize,
+}
+
+#[derive(Debug)]
+struct EventInner {
+	// events that were sent when nobody was listening
+	queue: VecDeque<EventArgs>,
+	// threads blocked in listen, they are sent events before asynchronous listeners
+	listeners: VecDeque<Tuid>,
+	alisteners: VecDeque<AsyncListener>,
+}
+
+#[derive(Debug)]
+pub struct Event {
+	id: Evid,
+	argc: usize,
+	// allocator the event is charged to
+	allocator: Arc<Allocator>,
+	// this lock is held while a thread blocks, and is released by the scheduler in ThreadState::atomic_process
+	inner: Mutex<EventInner>,
+}
+
+impl Event {
+	// returns InvlArgs if argc is greater than MAX_ARGS, or OutOfMem if the event can't be charged to allocator
+	pub fn new(argc: usize, flags: CapFlags, allocator: Arc<Allocator>) -> Result<Capability<Self>, SysErr> {
+		if argc > MAX_ARGS {
+			return Err(SysErr::InvlArgs);
+		}
+		allocator.charge(EVENT_PAGES)?;
+
+		let event = Arc::new(Event {
+			id: Evid::from(NEXT_EVID.fetch_add(1, Ordering::Relaxed)),
+			argc,
+			allocator,
+			inner: Mutex::new(EventInner {
+				queue: VecDeque::new(),
+				listeners: VecDeque::new(),
+				alisteners: VecDeque::new(),
+			}),
+		});
+
+		tlist.ensure(ThreadState::EventListen(Arc::as_ptr(&event)));
+
+		Ok(Capability::new(event, flags))
+	}
+
+	pub fn id(&self) -> Evid {
+		self.id
+	}
+
+	pub fn argc(&self) -> usize {
+		self.argc
+	}
+
+	// safety: only call in atomic_process which is called by scheduler
+	pub unsafe fn force_unlock(&self) {
+		self.inner.force_unlock();
+	}
+
+	// copies the arguments the event takes out of args, the rest are left as 0
+	fn event_args(&self, args: &[usize]) -> EventArgs {
+		let mut event_args = [0; MAX_ARGS];
+		let argc = min(self.argc, args.len());
+		event_args[..argc].copy_from_slice(&args[..argc]);
+		event_args
+	}
+
+	// asynchronously listens on the event, the handler will be called in the thread when an event is sent
+	// cid is the id of the event capability in the listening thread's process, and is passed to the handler
+	// inner must be locked for the whole check and insert, so another listener can't be added in between
+	fn alisten_locked(inner: &mut EventInner, tuid: Tuid, cid: CapId, handler: usize) {
+		if let Some(thread) = thread_get(tuid) {
+			while let Some(args) = inner.queue.pop_front() {
+				thread.push_async_event(AsyncEvent {
+					handler,
+					cid,
+					args,
+				});
+			}
+		}
+
+		inner.alisteners.push_back(AsyncListener {
+			tuid,
+			cid,
+			handler,
+		});
+	}
+
+	// returns InvlOp if the thread is already asynchronously listening
+	pub fn try_alisten(&self, tuid: Tuid, cid: CapId, handler: usize) -> SysErr {
+		let mut inner = self.inner.lock();
+		if inner.alisteners.iter().any(|l| l.tuid == tuid) {
+			return SysErr::InvlOp;
+		}
+
+		Self::alisten_locked(&mut inner, tuid, cid, handler);
+		SysErr::Ok
+	}
+
+	// like try_alisten, but succeeds without doing anything if the thread is already listening with the same handler
+	pub fn ensure_alisten(&self, tuid: Tuid, cid: CapId, handler: usize) -> SysErr {
+		let mut inner = self.inner.lock();
+		if let Some(listener) = inner.alisteners.iter().find(|l| l.tuid == tuid) {
+			return if listener.cid == cid && listener.handler == handler {
+				SysErr::Ok
+			} else {
+				SysErr::InvlOp
+			};
+		}
+
+		Self::alisten_locked(&mut inner, tuid, cid, handler);
+		SysErr::Ok
+	}
+
+	// stops the thread from asynchronously listening on the event
+	// returns InvlOp if the thread was not asynchronously listening
+	pub fn aabort(&self, tuid: Tuid) -> SysErr {
+		let mut inner = self.inner.lock();
+		match inner.alisteners.iter().position(|l| l.tuid == tuid) {
+			Some(index) => {
+				inner.alisteners.remove(index);
+				SysErr::Ok
+			},
+			None => SysErr::InvlOp,
+		}
+	}
+
+	// synchronously listens on the event, blocking until an event is sent
+	// if timeout is Some, OkTimeout is returned if no event is sent by timeout nanoseconds after boot
+	// if the thread is asynchronously listening on this event, the asynchronous listen is aborted
+	pub fn listen(&self, timeout: Option<u64>) -> Result<EventArgs, SysErr> {
+		let tuid = thread_c().tuid();
+		let mut inner = self.inner.lock();
+		inner.alisteners.retain(|l| l.tuid != tuid);
+
+		if let Some(args) = inner.queue.pop_front() {
+			return Ok(args);
+		}
+
+		inner.listeners.push_back(tuid);
+
+		// the scheduler unlocks inner once this thread is blocked,
+		// so no other thread can try to wake this thread before it is blocked
+		let state = match timeout {
+			Some(nsec) => ThreadState::EventSleep(self as *const _, nsec),
+			None => ThreadState::EventListen(self as *const _),
+		};
+		block(state);
+		core::mem::forget(inner);
+
+		// a sender removes the thread from listeners before waking it,
+		// so if it is still there the thread was woken by the timeout
+		let mut inner = self.inner.lock();
+		match inner.listeners.iter().position(|t| *t == tuid) {
+			Some(index) => {
+				inner.listeners.remove(index);
+				Err(SysErr::OkTimeout)
+			},
+			None => Ok(thread_c().event_args()),
+		}
+	}
+
+	// returns an event that was sent while nobody was listening, or OkUnreach if there are none
+	pub fn nblisten(&self) -> Result<EventArgs, SysErr> {
+		self.inner.lock().queue.pop_front().ok_or(SysErr::OkUnreach)
+	}
+
+	// sends an event with the given arguments, arguments past argc are ignored
+	// threads blocked in listen recieve events first, then asynchronous listeners take turns recieving events,
+	// and if nobody is listening the event is queued
+	pub fn send(&self, args: &[usize]) {
+		let event_args = self.event_args(args);
+		let mut inner = self.inner.lock();
+
+		while let Some(tuid) = inner.listeners.pop_front() {
+			// drop listeners whose thread has exited
+			if let Some(thread) = thread_get(tuid) {
+				thread.event_wake(event_args);
+				return;
+			}
+		}
+
+		while let Some(listener) = inner.alisteners.pop_front() {
+			// drop listeners whose thread has exited
+			if let Some(thread) = thread_get(listener.tuid) {
+				thread.push_async_event(AsyncEvent {
+					handler: listener.handler,
+					cid: listener.cid,
+					args: event_args,
+				});
+				inner.alisteners.push_back(listener);
+				return;
+			}
+		}
+
+		inner.queue.push_back(event_args);
+	}
+
+	// sends an event with the given arguments to every thread listening on the event
+	// unlike send, the event is not queued if nobody is listening
+	pub fn send_all(&self, args: &[usize]) {
+		let event_args = self.event_args(args);
+		let mut inner = self.inner.lock();
+
+		while let Some(tuid) = inner.listeners.pop_front() {
+			if let Some(thread) = thread_get(tuid) {
+				thread.event_wake(event_args);
+			}
+		}
+
+		inner.alisteners.retain(|listener| match thread_get(listener.tuid) {
+			Some(thread) => {
+				thread.push_async_event(AsyncEvent {
+					handler: listener.handler,
+					cid: listener.cid,
+					args: event_args,
+				});
+				true
+			},
+			None => false,
+		});
+	}
+}
+
+impl Drop for Event {
+	fn drop(&mut self) {
+		tlist.dealloc_state(ThreadState::EventListen(self as *const _));
+		self.allocator.uncharge(EVENT_PAGES);
+	}
+}
+
+impl CapObject for Event {
+	fn cap_object_type() -> CapObjectType {
+		CapObjectType::Event
+	}
+
+	fn inc_ref(&self) {}
+	fn dec_ref(&self) {}
+}
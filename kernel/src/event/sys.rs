@@ -0,0 +1,168 @@
+use sys_consts::options::EventOptions;
+
+use crate::uses::*;
+use crate::sysret;
+use crate::syscall::SyscallVals;
+use crate::syscall::udata::verify_ucanonical;
+use crate::cap::{CapId, CapFlags};
+use crate::sched::{proc_c, thread_c};
+use super::{Event, EventArgs, MAX_SEND_COUNT};
+
+// size of the area below the stack pointer that user code may use without moving the stack pointer
+const RED_ZONE_SIZE: usize = 128;
+
+// sets registers using the event listener abi, the event's cid is put in a1 and the arguments in a2-a9
+fn set_listen_regs(vals: &mut SyscallVals, cid: CapId, args: &EventArgs) {
+	vals.a1 = cid.into();
+	vals.a2 = args[0];
+	vals.a3 = args[1];
+	vals.a4 = args[2];
+	vals.a5 = args[3];
+	vals.a6 = args[4];
+	vals.a7 = args[5];
+	vals.a8 = args[6];
+	vals.a9 = args[7];
+}
+
+// sets the return values of a syscall using the event listener abi
+// on failure the error code is returned in bits 32-63 of a1, so a1 is not a valid event cid
+fn listen_ret(vals: &mut SyscallVals, cid: CapId, result: Result<EventArgs, SysErr>) {
+	match result {
+		Ok(args) => set_listen_regs(vals, cid, &args),
+		Err(err) => vals.a1 = err.num() << 32,
+	}
+}
+
+// called before returning from every syscall
+// if the thread has recieved an asynchronous event and is not already running an event handler,
+// the syscall returns into the event's handler instead, and eret returns to where the syscall would have returned
+pub fn async_handler_ret(vals: &mut SyscallVals) {
+	let event = match thread_c().start_async_handler(vals) {
+		Some(event) => event,
+		None => return,
+	};
+
+	// leave the red zone of the interrupted code alone, and align the stack as if the handler was called
+	// if the user stack pointer is too low for this, or the handler is not a user address, the event is not delivered
+	let rsp = vals.rsp.checked_sub(RED_ZONE_SIZE)
+		.and_then(|rsp| align_down(rsp, 16).checked_sub(8))
+		.filter(|rsp| verify_ucanonical(*rsp));
+	let rsp = match rsp {
+		Some(rsp) if verify_ucanonical(event.handler) => rsp,
+		_ => {
+			thread_c().end_async_handler();
+			return;
+		},
+	};
+
+	vals.rsp = rsp;
+	vals.rip = event.handler;
+	set_listen_regs(vals, event.cid, &event.args);
+}
+
+pub extern "C" fn event_new(vals: &mut SyscallVals) {
+	let flags = CapFlags::from_bits_truncate(vals.options as usize);
+
+	let allocator = match proc_c().allocators().fetch(CapId::from(vals.a1), CapFlags::PROD) {
+		Ok(allocator) => allocator,
+		Err(err) => sysret!(vals, err.num(), 0),
+	};
+
+	let event = match Event::new(vals.a2, flags, allocator) {
+		Ok(event) => event,
+		Err(err) => sysret!(vals, err.num(), 0),
+	};
+
+	let cid = proc_c().events().insert(event);
+	sysret!(vals, SysErr::Ok.num(), cid.into());
+}
+
+pub extern "C" fn event_argc(vals: &mut SyscallVals) {
+	match proc_c().events().fetch(CapId::from(vals.a1), CapFlags::READ) {
+		Ok(event) => sysret!(vals, SysErr::Ok.num(), event.argc()),
+		Err(err) => sysret!(vals, err.num(), 0),
+	}
+}
+
+pub extern "C" fn event_send(vals: &mut SyscallVals) {
+	let options = EventOptions::from_bits_truncate(vals.options);
+
+	if vals.a2 > MAX_SEND_COUNT {
+		sysret!(vals, SysErr::InvlArgs.num());
+	}
+
+	let event = match proc_c().events().fetch(CapId::from(vals.a1), CapFlags::WRITE) {
+		Ok(event) => event,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	let args = [vals.a3, vals.a4, vals.a5, vals.a6, vals.a7, vals.a8, vals.a9, vals.a10];
+	for _ in 0..vals.a2 {
+		if options.contains(EventOptions::SEND_ALL) {
+			event.send_all(&args);
+		} else {
+			event.send(&args);
+		}
+	}
+
+	sysret!(vals, SysErr::Ok.num());
+}
+
+pub extern "C" fn event_listen(vals: &mut SyscallVals) {
+	let options = EventOptions::from_bits_truncate(vals.options);
+	let cid = CapId::from(vals.a1);
+
+	let event = match proc_c().events().fetch(cid, CapFlags::PROD) {
+		Ok(event) => event,
+		Err(err) => sysret!(vals, err.num() << 32),
+	};
+
+	let timeout = if options.contains(EventOptions::TIMEOUT) {
+		Some(vals.a2 as u64)
+	} else {
+		None
+	};
+
+	listen_ret(vals, cid, event.listen(timeout));
+}
+
+pub extern "C" fn event_nblisten(vals: &mut SyscallVals) {
+	let cid = CapId::from(vals.a1);
+
+	let event = match proc_c().events().fetch(cid, CapFlags::PROD) {
+		Ok(event) => event,
+		Err(err) => sysret!(vals, err.num() << 32),
+	};
+
+	listen_ret(vals, cid, event.nblisten());
+}
+
+pub extern "C" fn event_alisten(vals: &mut SyscallVals) {
+	let cid = CapId::from(vals.a1);
+
+	let event = match proc_c().events().fetch(cid, CapFlags::PROD) {
+		Ok(event) => event,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	if !verify_ucanonical(vals.a2) {
+		sysret!(vals, SysErr::InvlArgs.num());
+	}
+
+	sysret!(vals, event.try_alisten(thread_c().tuid(), cid, vals.a2).num());
+}
+
+pub extern "C" fn event_aabort(vals: &mut SyscallVals) {
+	let event = match proc_c().events().fetch(CapId::from(vals.a1), CapFlags::PROD) {
+		Ok(event) => event,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	sysret!(vals, event.aabort(thread_c().tuid()).num());
+}
+
+pub extern "C" fn eret(vals: &mut SyscallVals) {
+	if let Some(regs) = thread_c().end_async_handler() {
+		*vals = regs;
+	}
+}
@@ -31,6 +31,8 @@ pub use ioapic::IoApic;
 pub static LAPIC_ADDR: AtomicUsize = AtomicUsize::new(0);
 pub static BSP_ID: AtomicU8 = AtomicU8::new(0);
 pub static IO_APIC: IMutex<IoApic> = IMutex::new(unsafe { IoApic::new() });
+// irq overrides from the acpi tables, needed to reroute irqs after init
+static IRQ_OVERRIDES: IMutex<IrqOverrides> = IMutex::new(IrqOverrides::new());
 
 #[derive(Debug, Clone, Copy, BitfieldSpecifier)]
 #[bits = 3]
@@ -140,7 +142,7 @@ pub unsafe fn init(madt: &Madt) -> Vec<u8> {
 	}
 	
 	// store irq overrides to make sure io apic is initialized first
-	let mut overrides = IrqOverrides::new();
+	let mut overrides = IRQ_OVERRIDES.lock();
 
 	// ap lapic ids
 	let mut ap_ids = Vec::new();
@@ -212,6 +214,7 @@ pub unsafe fn init(madt: &Madt) -> Vec<u8> {
 	}
 
 	drop(io_apic);
+	drop(overrides);
 
 	LAPIC_ADDR.store(lapic_addr, Ordering::Release);
 
@@ -222,6 +225,15 @@ pub unsafe fn init(madt: &Madt) -> Vec<u8> {
 	ap_ids
 }
 
+// masks or unmasks the irq with the vector irq, which is routed to the bsp
+// returns false if the irq is not connected to the io apic
+pub fn set_irq_masked(irq: u8, masked: bool) -> bool {
+	let over = IRQ_OVERRIDES.lock().get_irq(irq);
+	let entry = IrqEntry::from(irq, IoApicDest::To(BSP_ID.load(Ordering::Acquire)), over.polarity, over.trigger_mode)
+		.with_masked(masked);
+	IO_APIC.lock().set_irq_entry(over.sysint as u8, entry)
+}
+
 #[repr(C)]
 #[derive(Debug)]
 struct ApData {
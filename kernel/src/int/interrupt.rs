@@ -0,0 +1,251 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+
+use spin::Mutex;
+
+use crate::uses::*;
+use crate::cap::{CapFlags, CapObject, CapObjectType, Capability};
+use crate::event::Event;
+use crate::mem::allocator::Allocator;
+use crate::arch::x64::IntDisable;
+use crate::sched::{block, proc_get, tlist, Pid, Registers, ThreadState};
+use super::idt::*;
+use super::set_irq_masked;
+
+// bitmask of interrupt vectors, relative to IRQ_BASE, that have been claimed by an Interrupt object
+static CLAIMED: AtomicUsize = AtomicUsize::new(0);
+// bitmask of claimed interrupt vectors that have fired, but whose events have not been sent yet
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+// held while setting PENDING and waking int_dispatcher, and while int_dispatcher checks PENDING and blocks
+// it is released by the scheduler in ThreadState::atomic_process once int_dispatcher is blocked, so no wakeup is lost
+static WAIT_LOCK: Mutex<()> = Mutex::new(());
+
+// pages charged to the allocator of an interrupt object, for the kernel data used by the interrupt
+const INT_PAGES: usize = 1;
+
+// interrupt objects that currently exist, only used outside of interrupt handlers
+static interrupts: Mutex<BTreeMap<u8, Weak<Interrupt>>> = Mutex::new(BTreeMap::new());
+
+fn vec_bit(vec: u8) -> usize {
+	1 << (vec - IRQ_BASE)
+}
+
+// called by the interrupt stubs when a hardware interrupt occurs
+// the irq line is masked until userspace calls int_eoi, and the event is sent later by int_dispatcher,
+// because sending events locks spinlocks and allocates memory, which can't be done in an interrupt handler
+fn irq_fired(vec: u8) {
+	if CLAIMED.load(Ordering::Acquire) & vec_bit(vec) == 0 {
+		return;
+	}
+
+	set_irq_masked(vec, true);
+
+	let wait_lock = WAIT_LOCK.lock();
+	PENDING.fetch_or(vec_bit(vec), Ordering::AcqRel);
+	tlist.state_move(ThreadState::IntWait, ThreadState::Ready, 1);
+	drop(wait_lock);
+}
+
+// safety: only call in atomic_process which is called by scheduler
+pub unsafe fn force_unlock() {
+	WAIT_LOCK.force_unlock();
+}
+
+macro_rules! int_stub {
+	($name:ident, $vec:expr) => {
+		fn $name(_: &mut Registers, _: u64) -> bool {
+			irq_fired($vec);
+			false
+		}
+	};
+}
+
+int_stub!(int_stub_keyboard, IRQ_KEYBOARD);
+int_stub!(int_stub_serial_port_2, IRQ_SERIAL_PORT_2);
+int_stub!(int_stub_serial_port_1, IRQ_SERIAL_PORT_1);
+int_stub!(int_stub_parallel_port_2_3, IRQ_PARALLEL_PORT_2_3);
+int_stub!(int_stub_floppy_disk, IRQ_FLOPPY_DISK);
+int_stub!(int_stub_parallel_port_1, IRQ_PARALLEL_PORT_1);
+int_stub!(int_stub_clock, IRQ_CLOCK);
+int_stub!(int_stub_acpi, IRQ_ACPI);
+int_stub!(int_stub_none_1, IRQ_NONE_1);
+int_stub!(int_stub_none_2, IRQ_NONE_2);
+int_stub!(int_stub_mouse, IRQ_MOUSE);
+int_stub!(int_stub_co_processor, IRQ_CO_PROCESSOR);
+int_stub!(int_stub_primary_ata, IRQ_PRIMARY_ATA);
+int_stub!(int_stub_secondary_ata, IRQ_SECONDARY_ATA);
+
+// interrupt vectors that can be used by userspace, and their stubs
+// the timer is used by the scheduler, so it is not included
+const INT_STUBS: [(u8, IntHandlerFunc); 14] = [
+	(IRQ_KEYBOARD, int_stub_keyboard),
+	(IRQ_SERIAL_PORT_2, int_stub_serial_port_2),
+	(IRQ_SERIAL_PORT_1, int_stub_serial_port_1),
+	(IRQ_PARALLEL_PORT_2_3, int_stub_parallel_port_2_3),
+	(IRQ_FLOPPY_DISK, int_stub_floppy_disk),
+	(IRQ_PARALLEL_PORT_1, int_stub_parallel_port_1),
+	(IRQ_CLOCK, int_stub_clock),
+	(IRQ_ACPI, int_stub_acpi),
+	(IRQ_NONE_1, int_stub_none_1),
+	(IRQ_NONE_2, int_stub_none_2),
+	(IRQ_MOUSE, int_stub_mouse),
+	(IRQ_CO_PROCESSOR, int_stub_co_processor),
+	(IRQ_PRIMARY_ATA, int_stub_primary_ata),
+	(IRQ_SECONDARY_ATA, int_stub_secondary_ata),
+];
+
+// sends the events of interrupts that have fired
+fn int_dispatcher() {
+	loop {
+		let int_disable = IntDisable::new();
+		let wait_lock = WAIT_LOCK.lock();
+
+		let pending = PENDING.swap(0, Ordering::AcqRel);
+		if pending == 0 {
+			// the scheduler unlocks wait_lock once this thread is blocked,
+			// so an interrupt on another cpu can't try to wake this thread before it is blocked
+			block(ThreadState::IntWait);
+			core::mem::forget(wait_lock);
+			drop(int_disable);
+			continue;
+		}
+
+		drop(wait_lock);
+		drop(int_disable);
+
+		for (vec, _) in INT_STUBS.iter() {
+			if pending & vec_bit(*vec) == 0 {
+				continue;
+			}
+
+			let interrupt = interrupts.lock().get(vec).and_then(|interrupt| interrupt.upgrade());
+			if let Some(interrupt) = interrupt {
+				interrupt.fire();
+			}
+		}
+	}
+}
+
+// must be called on the bootstrap processor after the scheduler and the apic are initialized,
+// because all irqs are routed to the bootstrap processor
+pub fn init() -> Result<(), Err> {
+	for (vec, stub) in INT_STUBS.iter() {
+		Handler::Normal(*stub).register(*vec)?;
+		// lines are unmasked when an interrupt object claims them
+		set_irq_masked(*vec, true);
+	}
+
+	// ok to unwrap, the kernel process always exists
+	let kernel_proc = proc_get(Pid::from(0)).unwrap();
+	kernel_proc.new_thread(int_dispatcher as usize, Some("int_dispatcher".to_string()))?;
+
+	Ok(())
+}
+
+// permission to create interrupt objects
+#[derive(Debug)]
+pub struct IntAllocator;
+
+impl IntAllocator {
+	pub fn new() -> Capability<Self> {
+		Capability::new(Arc::new(IntAllocator), CapFlags::READ | CapFlags::PROD | CapFlags::WRITE)
+	}
+}
+
+impl CapObject for IntAllocator {
+	fn cap_object_type() -> CapObjectType {
+		CapObjectType::IntAllocator
+	}
+
+	fn inc_ref(&self) {}
+	fn dec_ref(&self) {}
+}
+
+#[derive(Debug)]
+pub struct Interrupt {
+	vec: u8,
+	// event that is sent whenever the interrupt fires
+	event: Mutex<Option<Arc<Event>>>,
+	// allocator the interrupt is charged to
+	allocator: Arc<Allocator>,
+}
+
+impl Interrupt {
+	// returns InvlArgs if vec can't be used by userspace, InvlOp if another interrupt object already uses vec,
+	// or OutOfMem if the interrupt can't be charged to allocator
+	pub fn new(vec: usize, flags: CapFlags, allocator: Arc<Allocator>) -> Result<Capability<Self>, SysErr> {
+		let vec = INT_STUBS
+			.iter()
+			.map(|(vec, _)| *vec)
+			.find(|v| *v as usize == vec)
+			.ok_or(SysErr::InvlArgs)?;
+
+		let mut ints = interrupts.lock();
+		if ints.get(&vec).and_then(|interrupt| interrupt.upgrade()).is_some() {
+			return Err(SysErr::InvlOp);
+		}
+		allocator.charge(INT_PAGES)?;
+
+		let interrupt = Arc::new(Interrupt {
+			vec,
+			event: Mutex::new(None),
+			allocator,
+		});
+		ints.insert(vec, Arc::downgrade(&interrupt));
+
+		CLAIMED.fetch_or(vec_bit(vec), Ordering::AcqRel);
+		set_irq_masked(vec, false);
+
+		Ok(Capability::new(interrupt, flags))
+	}
+
+	pub fn vec(&self) -> u8 {
+		self.vec
+	}
+
+	// sets the event that is sent whenever the interrupt fires
+	pub fn bind(&self, event: Arc<Event>) {
+		*self.event.lock() = Some(event);
+	}
+
+	// unmasks the irq line, so the interrupt can fire again
+	pub fn eoi(&self) {
+		set_irq_masked(self.vec, false);
+	}
+
+	// sends the bound event, with the interrupt vector as its argument
+	fn fire(&self) {
+		if let Some(event) = self.event.lock().as_ref() {
+			event.send(&[self.vec as usize]);
+		}
+	}
+}
+
+impl Drop for Interrupt {
+	fn drop(&mut self) {
+		let mut ints = interrupts.lock();
+
+		// another interrupt object may have claimed the vector after the last reference to this one was dropped
+		if ints.get(&self.vec).map_or(true, |interrupt| interrupt.upgrade().is_none()) {
+			ints.remove(&self.vec);
+			set_irq_masked(self.vec, true);
+			CLAIMED.fetch_and(!vec_bit(self.vec), Ordering::AcqRel);
+		}
+
+		self.allocator.uncharge(INT_PAGES);
+	}
+}
+
+impl CapObject for Interrupt {
+	fn cap_object_type() -> CapObjectType {
+		CapObjectType::Interrupt
+	}
+
+	fn inc_ref(&self) {}
+	fn dec_ref(&self) {}
+
+	fn info(&self) -> [usize; 2] {
+		[self.vec as usize, 0]
+	}
+}
@@ -7,6 +7,8 @@ pub mod idt;
 pub mod pic;
 pub mod apic;
 pub mod manager;
+pub mod interrupt;
+pub mod sys;
 
 crate::make_id_type!(Irq, u8);
 
@@ -24,3 +26,14 @@ pub extern "C" fn eoi(irq: u8)
 		outb(PICM_COMMAND, PIC_EOI);
 	}
 }
+
+// masks or unmasks the irq line that sends interrupts with the vector vec
+// returns false if no irq line sends interrupts with vec
+pub fn set_irq_masked(vec: u8, masked: bool) -> bool
+{
+	if config::use_apic() {
+		apic::set_irq_masked(vec, masked)
+	} else {
+		pic::set_irq_masked(vec, masked)
+	}
+}
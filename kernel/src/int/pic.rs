@@ -58,6 +58,26 @@ pub fn remap(moffset: u8, soffset: u8)
 	outb(PICS_DATA, s2);
 }
 
+// masks or unmasks the irq that is mapped to vec, assuming the pics are mapped to PICM_OFFSET and PICS_OFFSET
+// returns false if vec is not mapped to an irq
+pub fn set_irq_masked(vec: u8, masked: bool) -> bool {
+	let (port, irq) = if vec >= PICM_OFFSET && vec < PICS_OFFSET {
+		(PICM_DATA, vec - PICM_OFFSET)
+	} else if vec >= PICS_OFFSET && vec < PICS_OFFSET + 8 {
+		(PICS_DATA, vec - PICS_OFFSET)
+	} else {
+		return false;
+	};
+
+	let mask = inb(port);
+	if masked {
+		outb(port, mask | (1 << irq));
+	} else {
+		outb(port, mask & !(1 << irq));
+	}
+	true
+}
+
 // disable the pic
 pub fn disable() {
 	// need to remap it to higher interrupt number so spurious interrupts dont cause problems
@@ -0,0 +1,60 @@
+use crate::uses::*;
+use crate::sysret;
+use crate::syscall::SyscallVals;
+use crate::cap::{CapId, CapFlags};
+use crate::sched::proc_c;
+use super::interrupt::Interrupt;
+
+pub extern "C" fn int_new(vals: &mut SyscallVals) {
+	let flags = CapFlags::from_bits_truncate(vals.options as usize);
+
+	let allocator = match proc_c().allocators().fetch(CapId::from(vals.a1), CapFlags::PROD) {
+		Ok(allocator) => allocator,
+		Err(err) => sysret!(vals, err.num(), 0),
+	};
+
+	if let Err(err) = proc_c().int_allocators().fetch(CapId::from(vals.a2), CapFlags::PROD) {
+		sysret!(vals, err.num(), 0);
+	}
+
+	match Interrupt::new(vals.a3, flags, allocator) {
+		Ok(interrupt) => {
+			let cid = proc_c().interrupts().insert(interrupt);
+			sysret!(vals, SysErr::Ok.num(), cid.into());
+		},
+		Err(err) => sysret!(vals, err.num(), 0),
+	}
+}
+
+pub extern "C" fn int_vector(vals: &mut SyscallVals) {
+	match proc_c().interrupts().fetch(CapId::from(vals.a1), CapFlags::READ) {
+		Ok(interrupt) => sysret!(vals, SysErr::Ok.num(), interrupt.vec() as usize),
+		Err(err) => sysret!(vals, err.num(), 0),
+	}
+}
+
+pub extern "C" fn int_bind(vals: &mut SyscallVals) {
+	let interrupt = match proc_c().interrupts().fetch(CapId::from(vals.a1), CapFlags::WRITE) {
+		Ok(interrupt) => interrupt,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	// the kernel sends the event on behalf of the caller, so the caller must be allowed to send it
+	let event = match proc_c().events().fetch(CapId::from(vals.a2), CapFlags::WRITE) {
+		Ok(event) => event,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	interrupt.bind(event);
+	sysret!(vals, SysErr::Ok.num());
+}
+
+pub extern "C" fn int_eoi(vals: &mut SyscallVals) {
+	match proc_c().interrupts().fetch(CapId::from(vals.a1), CapFlags::WRITE) {
+		Ok(interrupt) => {
+			interrupt.eoi();
+			sysret!(vals, SysErr::Ok.num());
+		},
+		Err(err) => sysret!(vals, err.num()),
+	}
+}
@@ -1,13 +1,138 @@
 use crate::uses::*;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use alloc::collections::VecDeque;
-use crate::cap::{CapObject, CapObjectType};
-use crate::sched::Tuid;
+use alloc::sync::Arc;
+use spin::{Mutex, MutexGuard};
+use crate::cap::{CapId, CapObject, CapObjectType, Capability, CapFlags};
+use crate::event::Event;
+use crate::mem::allocator::Allocator;
+use crate::sched::{
+	block, proc_c, thread_c, thread_get, tlist, Process, Thread, ThreadRef, ThreadState, Tuid,
+};
 use super::Ipcid;
+use super::msg::{self, MsgBuf, MsgProps};
+
+static NEXT_IPCID: AtomicUsize = AtomicUsize::new(0);
+
+// pages charged to the allocator of a channel, for the kernel data used by the channel
+const CHANNEL_PAGES: usize = 1;
+
+// the event an asynchronous operation signals when it completes
+#[derive(Debug, Clone)]
+pub struct AsyncSignal {
+	event: Arc<Event>,
+	// cid of the message buffer used by the operation, which is the first argument sent to the event
+	msg_cid: CapId,
+}
+
+impl AsyncSignal {
+	pub fn new(event: Arc<Event>, msg_cid: CapId) -> Self {
+		AsyncSignal {
+			event,
+			msg_cid,
+		}
+	}
+
+	// badge is the badge of the recieved message, which is the second argument sent to the event, or 0 if nothing was recieved
+	pub fn signal(&self, badge: usize) {
+		self.event.send(&[self.msg_cid.into(), badge]);
+	}
+}
 
 #[derive(Debug)]
 pub struct IpcWaitInner {
 	tuid: Tuid,
-	msg_buf: VirtAddr,
+	msg_buf: MsgBuf,
+	// channel the reciever should reply to, only used by call and acall
+	reply: Option<Arc<Channel>>,
+	// only used by asynchronous operations
+	signal: Option<AsyncSignal>,
+	// badge of the capability a sender used, only used by senders
+	// recievers are given the badge when the message is transferred
+	badge: usize,
+}
+
+impl IpcWaitInner {
+	pub fn new(tuid: Tuid, msg_buf: MsgBuf) -> Self {
+		IpcWaitInner {
+			tuid,
+			msg_buf,
+			reply: None,
+			signal: None,
+			badge: 0,
+		}
+	}
+
+	pub fn with_reply(tuid: Tuid, msg_buf: MsgBuf, reply: Arc<Channel>) -> Self {
+		IpcWaitInner {
+			tuid,
+			msg_buf,
+			reply: Some(reply),
+			signal: None,
+			badge: 0,
+		}
+	}
+
+	pub fn new_async(
+		tuid: Tuid,
+		msg_buf: MsgBuf,
+		reply: Option<Arc<Channel>>,
+		signal: AsyncSignal,
+	) -> Self {
+		IpcWaitInner {
+			tuid,
+			msg_buf,
+			reply,
+			signal: Some(signal),
+			badge: 0,
+		}
+	}
+
+	pub fn badged(mut self, badge: usize) -> Self {
+		self.badge = badge;
+		self
+	}
+
+	// returns None if the waiting thread or its process exited while it was waiting
+	fn thread(&self) -> Option<(ThreadRef, Arc<Process>)> {
+		let thread = thread_get(self.tuid)?;
+		let process = thread.process()?;
+		Some((thread, process))
+	}
+
+	// finishes the operation once its message has been transferred
+	// synchronous operations wake the thread, and asynchronous operations signal their event
+	// if the operation was a call that succeeded, it instead starts waiting for the reply
+	// if handoff is true, the scheduler will switch directly to a woken thread the next time it runs
+	// badge is the badge of the message recieved by the operation, or 0 if it was not a recieve
+	fn complete(self, thread: &Thread, err: SysErr, handoff: bool, badge: usize) {
+		let IpcWaitInner {
+			tuid,
+			msg_buf,
+			reply,
+			signal,
+			..
+		} = self;
+
+		match (reply, signal) {
+			(Some(reply), signal) if err == SysErr::Ok => match (reply.lock_waiting(), signal) {
+				(Some(mut reply_waiting), Some(signal)) => {
+					let inner = IpcWaitInner::new_async(tuid, msg_buf, None, signal);
+					reply_waiting.push_back(IpcWait::AsyncRecv(inner));
+				},
+				(Some(mut reply_waiting), None) => {
+					reply_waiting.push_back(IpcWait::Recv(IpcWaitInner::new(tuid, msg_buf)));
+					thread.ipc_move(&reply);
+				},
+				// the reciever destroyed the reply capability before the caller started waiting for the reply
+				(None, Some(signal)) => signal.signal(0),
+				(None, None) => thread.ipc_wake(SysErr::InvlId),
+			},
+			(_, Some(signal)) => signal.signal(badge),
+			(_, None) if handoff => thread.ipc_handoff(err),
+			(_, None) => thread.ipc_wake(err),
+		}
+	}
 }
 
 #[derive(Debug)]
@@ -18,9 +143,444 @@ pub enum IpcWait {
 	AsyncRecv(IpcWaitInner),
 }
 
+impl IpcWait {
+	fn is_send(&self) -> bool {
+		matches!(self, Self::Send(_) | Self::AsyncSend(_))
+	}
+
+	fn into_inner(self) -> IpcWaitInner {
+		match self {
+			Self::Send(inner) | Self::Recv(inner) | Self::AsyncSend(inner) | Self::AsyncRecv(inner) => inner,
+		}
+	}
+}
+
 #[derive(Debug)]
 pub struct Channel {
-	waiting: VecDeque<IpcWait>,
+	id: Ipcid,
+	props: MsgProps,
+	// if set, non blocking operations return Obscured instead of revealing if another thread was waiting
+	sc_resist: bool,
+	// threads waiting on the channel are either all senders or all recievers
+	// this lock is held while a thread blocks, and is released by the scheduler in ThreadState::atomic_process
+	waiting: Mutex<VecDeque<IpcWait>>,
+	// number of strong capabilities referencing this channel
+	cap_refs: AtomicUsize,
+	// set to false when the last strong capability is destroyed, only changed while waiting is locked
+	alive: AtomicBool,
+	// allocator the channel is charged to, reply channels are charged to the allocator of the channel that was called
+	allocator: Arc<Allocator>,
+}
+
+impl Channel {
+	// returns OutOfMem if the channel can't be charged to allocator
+	pub fn new(
+		props: MsgProps,
+		sc_resist: bool,
+		flags: CapFlags,
+		allocator: Arc<Allocator>,
+	) -> Result<Capability<Self>, SysErr> {
+		Ok(Capability::new(Self::new_object(props, sc_resist, allocator)?, flags))
+	}
+
+	// makes a channel with no capabilities, used for reply channels, which only get a capability once a message is sent
+	fn new_object(props: MsgProps, sc_resist: bool, allocator: Arc<Allocator>) -> Result<Arc<Self>, SysErr> {
+		allocator.charge(CHANNEL_PAGES)?;
+
+		let out = Arc::new(Channel {
+			id: Ipcid::from(NEXT_IPCID.fetch_add(1, Ordering::Relaxed)),
+			props,
+			sc_resist,
+			waiting: Mutex::new(VecDeque::new()),
+			cap_refs: AtomicUsize::new(0),
+			alive: AtomicBool::new(true),
+			allocator,
+		});
+
+		tlist.ensure(ThreadState::Listening(Arc::as_ptr(&out)));
+
+		Ok(out)
+	}
+
+	pub fn id(&self) -> Ipcid {
+		self.id
+	}
+
+	// maximum message size and number of capabilities that can be sent in 1 message
+	pub fn msg_props(&self) -> MsgProps {
+		self.props
+	}
+
+	pub fn sc_resist(&self) -> bool {
+		self.sc_resist
+	}
+
+	// returns false once the last strong capability to the channel has been destroyed
+	pub fn is_alive(&self) -> bool {
+		self.alive.load(Ordering::Acquire)
+	}
+
+	// returns None if the channel was destroyed, in which case no thread can wait on it anymore
+	fn lock_waiting(&self) -> Option<MutexGuard<VecDeque<IpcWait>>> {
+		let waiting = self.waiting.lock();
+		if self.alive.load(Ordering::Acquire) {
+			Some(waiting)
+		} else {
+			None
+		}
+	}
+
+	// called when the last strong capability is destroyed
+	// every waiting thread is woken with InvlId, and every waiting asynchronous operation is signalled
+	fn destroy(&self) {
+		let mut waiting = self.waiting.lock();
+		self.alive.store(false, Ordering::Release);
+		let waiters = core::mem::take(&mut *waiting);
+		drop(waiting);
+
+		for wait in waiters {
+			let inner = wait.into_inner();
+			if let Some((thread, _)) = inner.thread() {
+				inner.complete(&thread, SysErr::InvlId, false, 0);
+			}
+		}
+	}
+
+	// safety: only call in atomic_process which is called by scheduler
+	pub unsafe fn force_unlock(&self) {
+		self.waiting.force_unlock();
+	}
+
+	// pops the first waiting sender if send is true, or reciever if send is false, whose thread is still alive
+	fn pop_waiter(
+		waiting: &mut VecDeque<IpcWait>,
+		send: bool,
+	) -> Option<(IpcWaitInner, ThreadRef, Arc<Process>)> {
+		while let Some(wait) = waiting.front() {
+			if wait.is_send() != send {
+				break;
+			}
+
+			// ok to unwrap, front returned Some
+			let inner = waiting.pop_front().unwrap().into_inner();
+			if let Some((thread, process)) = inner.thread() {
+				return Some((inner, thread, process));
+			}
+		}
+		None
+	}
+
+	// sends a message to a reciever that was waiting on this channel, and completes the reciever's operation
+	fn send_to(
+		&self,
+		msg_buf: &MsgBuf,
+		badge: usize,
+		recv: IpcWaitInner,
+		thread: ThreadRef,
+		recv_proc: Arc<Process>,
+		handoff: bool,
+	) -> SysErr {
+		let err = msg::transfer(
+			msg_buf,
+			&proc_c(),
+			&recv.msg_buf,
+			&thread,
+			&recv_proc,
+			self.props,
+			None,
+			badge,
+		);
+		recv.complete(&thread, err, handoff, badge);
+		err
+	}
+
+	// recieves a message from a sender that was waiting on this channel, and completes the sender's operation
+	fn recv_from(
+		&self,
+		msg_buf: &MsgBuf,
+		send: IpcWaitInner,
+		thread: ThreadRef,
+		send_proc: Arc<Process>,
+	) -> SysErr {
+		let err = msg::transfer(
+			&send.msg_buf,
+			&send_proc,
+			msg_buf,
+			&thread_c(),
+			&proc_c(),
+			self.props,
+			send.reply.as_ref(),
+			send.badge,
+		);
+		send.complete(&thread, err, false, 0);
+		err
+	}
+
+	// the return value of non blocking operations, which hides whether the operation happened if sc_resist is set
+	fn nb_result(&self, err: SysErr) -> SysErr {
+		match err {
+			SysErr::Ok | SysErr::OkUnreach if self.sc_resist => SysErr::Obscured,
+			_ => err,
+		}
+	}
+
+	// sends the message in msg_buf, blocking until another thread recieves it
+	// badge is the badge of the capability used to send the message
+	pub fn send(&self, msg_buf: MsgBuf, badge: usize) -> SysErr {
+		let mut waiting = match self.lock_waiting() {
+			Some(waiting) => waiting,
+			None => return SysErr::InvlId,
+		};
+
+		match Self::pop_waiter(&mut waiting, false) {
+			Some((recv, thread, recv_proc)) => {
+				drop(waiting);
+				self.send_to(&msg_buf, badge, recv, thread, recv_proc, false)
+			},
+			None => {
+				let inner = IpcWaitInner::new(thread_c().tuid(), msg_buf).badged(badge);
+				waiting.push_back(IpcWait::Send(inner));
+				self.block(waiting)
+			},
+		}
+	}
+
+	// recieves a message into msg_buf, blocking until another thread sends one
+	pub fn recv(&self, msg_buf: MsgBuf) -> SysErr {
+		let mut waiting = match self.lock_waiting() {
+			Some(waiting) => waiting,
+			None => return SysErr::InvlId,
+		};
+
+		match Self::pop_waiter(&mut waiting, true) {
+			Some((send, thread, send_proc)) => {
+				drop(waiting);
+				self.recv_from(&msg_buf, send, thread, send_proc)
+			},
+			None => {
+				waiting.push_back(IpcWait::Recv(IpcWaitInner::new(thread_c().tuid(), msg_buf)));
+				self.block(waiting)
+			},
+		}
+	}
+
+	// sends the message in msg_buf if another thread is waiting to recieve, otherwise returns OkUnreach
+	pub fn nbsend(&self, msg_buf: MsgBuf, badge: usize) -> SysErr {
+		let mut waiting = match self.lock_waiting() {
+			Some(waiting) => waiting,
+			None => return SysErr::InvlId,
+		};
+
+		let err = match Self::pop_waiter(&mut waiting, false) {
+			Some((recv, thread, recv_proc)) => {
+				drop(waiting);
+				self.send_to(&msg_buf, badge, recv, thread, recv_proc, false)
+			},
+			None => SysErr::OkUnreach,
+		};
+
+		self.nb_result(err)
+	}
+
+	// recieves a message into msg_buf if another thread is waiting to send, otherwise returns OkUnreach
+	pub fn nbrecv(&self, msg_buf: MsgBuf) -> SysErr {
+		let mut waiting = match self.lock_waiting() {
+			Some(waiting) => waiting,
+			None => return SysErr::InvlId,
+		};
+
+		let err = match Self::pop_waiter(&mut waiting, true) {
+			Some((send, thread, send_proc)) => {
+				drop(waiting);
+				self.recv_from(&msg_buf, send, thread, send_proc)
+			},
+			None => SysErr::OkUnreach,
+		};
+
+		self.nb_result(err)
+	}
+
+	// sends the message in msg_buf along with a new reply channel, and then waits for a reply on the reply channel
+	// the reply is recieved into msg_buf
+	pub fn call(&self, msg_buf: MsgBuf, badge: usize) -> SysErr {
+		let reply = match Channel::new_object(self.props, self.sc_resist, self.allocator.clone()) {
+			Ok(reply) => reply,
+			Err(err) => return err,
+		};
+		let mut waiting = match self.lock_waiting() {
+			Some(waiting) => waiting,
+			None => return SysErr::InvlId,
+		};
+
+		let (recv, thread, recv_proc) = match Self::pop_waiter(&mut waiting, false) {
+			Some(recv) => recv,
+			None => {
+				// the reciever will move this thread to wait on the reply channel when it recieves the message
+				let tuid = thread_c().tuid();
+				let inner = IpcWaitInner::with_reply(tuid, msg_buf, reply.clone()).badged(badge);
+				waiting.push_back(IpcWait::Send(inner));
+				return self.block(waiting);
+			},
+		};
+		drop(waiting);
+
+		let err = msg::transfer(
+			&msg_buf,
+			&proc_c(),
+			&recv.msg_buf,
+			&thread,
+			&recv_proc,
+			self.props,
+			Some(&reply),
+			badge,
+		);
+		if err != SysErr::Ok {
+			recv.complete(&thread, err, false, badge);
+			return err;
+		}
+
+		// start waiting on the reply channel before the reciever can run, so the reply can't be missed,
+		// and switch directly to the reciever
+		let mut reply_waiting = match reply.lock_waiting() {
+			Some(reply_waiting) => reply_waiting,
+			None => {
+				recv.complete(&thread, err, false, badge);
+				return SysErr::InvlId;
+			},
+		};
+		reply_waiting.push_back(IpcWait::Recv(IpcWaitInner::new(thread_c().tuid(), msg_buf)));
+		recv.complete(&thread, err, true, badge);
+		reply.block(reply_waiting)
+	}
+
+	// asynchronously sends the message in msg_buf, signal is signalled once the message is recieved
+	pub fn asend(&self, msg_buf: MsgBuf, badge: usize, signal: AsyncSignal) -> SysErr {
+		let mut waiting = match self.lock_waiting() {
+			Some(waiting) => waiting,
+			None => return SysErr::InvlId,
+		};
+
+		match Self::pop_waiter(&mut waiting, false) {
+			Some((recv, thread, recv_proc)) => {
+				drop(waiting);
+				let err = self.send_to(&msg_buf, badge, recv, thread, recv_proc, false);
+				signal.signal(0);
+				err
+			},
+			None => {
+				let inner = IpcWaitInner::new_async(thread_c().tuid(), msg_buf, None, signal).badged(badge);
+				waiting.push_back(IpcWait::AsyncSend(inner));
+				SysErr::Ok
+			},
+		}
+	}
+
+	// asynchronously recieves a message into msg_buf, signal is signalled once a message is recieved
+	pub fn arecv(&self, msg_buf: MsgBuf, signal: AsyncSignal) -> SysErr {
+		let mut waiting = match self.lock_waiting() {
+			Some(waiting) => waiting,
+			None => return SysErr::InvlId,
+		};
+
+		match Self::pop_waiter(&mut waiting, true) {
+			Some((send, thread, send_proc)) => {
+				drop(waiting);
+				let badge = send.badge;
+				let err = self.recv_from(&msg_buf, send, thread, send_proc);
+				signal.signal(badge);
+				err
+			},
+			None => {
+				let inner = IpcWaitInner::new_async(thread_c().tuid(), msg_buf, None, signal);
+				waiting.push_back(IpcWait::AsyncRecv(inner));
+				SysErr::Ok
+			},
+		}
+	}
+
+	// asynchronously calls the channel, signal is signalled once the reply is recieved into msg_buf
+	pub fn acall(&self, msg_buf: MsgBuf, badge: usize, signal: AsyncSignal) -> SysErr {
+		let reply = match Channel::new_object(self.props, self.sc_resist, self.allocator.clone()) {
+			Ok(reply) => reply,
+			Err(err) => return err,
+		};
+		let mut waiting = match self.lock_waiting() {
+			Some(waiting) => waiting,
+			None => return SysErr::InvlId,
+		};
+
+		let (recv, thread, recv_proc) = match Self::pop_waiter(&mut waiting, false) {
+			Some(recv) => recv,
+			None => {
+				let tuid = thread_c().tuid();
+				let inner = IpcWaitInner::new_async(tuid, msg_buf, Some(reply), signal).badged(badge);
+				waiting.push_back(IpcWait::AsyncSend(inner));
+				return SysErr::Ok;
+			},
+		};
+		drop(waiting);
+
+		let err = msg::transfer(
+			&msg_buf,
+			&proc_c(),
+			&recv.msg_buf,
+			&thread,
+			&recv_proc,
+			self.props,
+			Some(&reply),
+			badge,
+		);
+
+		if err == SysErr::Ok {
+			match reply.lock_waiting() {
+				Some(mut reply_waiting) => {
+					let inner = IpcWaitInner::new_async(thread_c().tuid(), msg_buf, None, signal);
+					reply_waiting.push_back(IpcWait::AsyncRecv(inner));
+				},
+				None => signal.signal(0),
+			}
+		}
+
+		recv.complete(&thread, err, false, badge);
+		err
+	}
+
+	// sends a reply to a thread waiting in call, without blocking
+	// the scheduler will switch directly to the calling thread the next time it runs
+	// returns OkUnreach if no thread is waiting on this channel
+	pub fn reply(&self, msg_buf: MsgBuf) -> SysErr {
+		let mut waiting = match self.lock_waiting() {
+			Some(waiting) => waiting,
+			None => return SysErr::InvlId,
+		};
+
+		match Self::pop_waiter(&mut waiting, false) {
+			Some((recv, thread, recv_proc)) => {
+				drop(waiting);
+				// reply channels are made by the kernel, so they never have a badge
+				self.send_to(&msg_buf, 0, recv, thread, recv_proc, true)
+			},
+			None => SysErr::OkUnreach,
+		}
+	}
+
+	// blocks the current thread until another thread completes the operation it queued,
+	// and returns the error code set by that thread
+	fn block(&self, waiting: MutexGuard<VecDeque<IpcWait>>) -> SysErr {
+		// the scheduler unlocks waiting once this thread is in the listening queue,
+		// so no other thread can try to wake this thread before it is blocked
+		block(ThreadState::Listening(self as *const _));
+		core::mem::forget(waiting);
+
+		thread_c().ipc_result()
+	}
+}
+
+impl Drop for Channel {
+	fn drop(&mut self) {
+		tlist.dealloc_state(ThreadState::Listening(self as *const _));
+		self.allocator.uncharge(CHANNEL_PAGES);
+	}
 }
 
 impl CapObject for Channel {
@@ -28,6 +588,17 @@ impl CapObject for Channel {
 		CapObjectType::Channel
 	}
 
-	fn inc_ref(&self) {}
-	fn dec_ref(&self) {}
+	fn inc_ref(&self) {
+		self.cap_refs.fetch_add(1, Ordering::AcqRel);
+	}
+
+	fn dec_ref(&self) {
+		if self.cap_refs.fetch_sub(1, Ordering::AcqRel) == 1 {
+			self.destroy();
+		}
+	}
+
+	fn info(&self) -> [usize; 2] {
+		[self.props.msg_size, self.props.max_caps]
+	}
 }
@@ -1,3 +1,6 @@
 pub mod channel;
+pub mod msg;
+pub mod registry;
+pub mod sys;
 
 crate::make_id_type!(Ipcid);
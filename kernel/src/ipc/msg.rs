@@ -0,0 +1,322 @@
+use core::cmp::min;
+
+use bitflags::bitflags;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::uses::*;
+use crate::cap::{CapFlags, CapId, CapObjectType, Capability};
+use crate::mem::shared_mem::SharedMem;
+use crate::sched::{Process, Thread};
+use super::channel::Channel;
+
+// number of words in the message header
+pub const HEADER_LEN: usize = 3;
+
+const LEN_WORD: usize = 0;
+const FLAGS_WORD: usize = 1;
+const REPLY_WORD: usize = 2;
+
+// properties of a channel which limit the messages sent over it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsgProps {
+	// maximum message size in words, including the header, if 0 no messages are sent
+	pub msg_size: usize,
+	// maximum number of capabilities that can be sent in 1 message
+	pub max_caps: usize,
+}
+
+bitflags! {
+	pub struct MsgFlags: usize {
+		const REPLY = 1;
+		// memory capabilities sent in the message are mapped into the reciever's address space,
+		// and the address each capability was mapped at is written after the capabilities, or 0 if it was not mapped
+		const MAP = 1 << 1;
+		// capabilities are moved out of the sending process instead of being cloned,
+		// so the reciever becomes the only owner of them, and memory is unmapped from the sender
+		const MOVE = 1 << 2;
+	}
+}
+
+// a message header read from a message buffer
+#[derive(Debug, Clone, Copy)]
+pub struct MsgHeader {
+	// length of the message in words, including the header, clamped to the capacity of the buffer
+	pub len: usize,
+	pub flags: MsgFlags,
+	// number of capabilities in the message, clamped to the length of the message
+	pub cap_count: usize,
+}
+
+impl MsgHeader {
+	// index of the first data word
+	pub fn data_start(&self) -> usize {
+		HEADER_LEN + self.cap_count
+	}
+
+	// number of data words in the message
+	pub fn data_len(&self) -> usize {
+		self.len.saturating_sub(self.data_start())
+	}
+}
+
+// a view of a Mem capability's memory being used as a message buffer
+// NOTE: userspace can write to the buffer at any time, so the header is only read once with header,
+// and lengths from it are clamped before use
+#[derive(Debug, Clone, Copy)]
+pub struct Message<'a> {
+	mem: &'a SharedMem,
+	size: usize,
+}
+
+impl<'a> Message<'a> {
+	// only the first max_size words of the memory are used
+	// returns None if that is not big enough to hold a message
+	pub fn new(mem: &'a SharedMem, max_size: usize) -> Option<Self> {
+		let out = Message {
+			mem,
+			size: min(mem.size() / size_of::<usize>(), max_size),
+		};
+
+		if out.capacity() < HEADER_LEN {
+			None
+		} else {
+			Some(out)
+		}
+	}
+
+	// size of the buffer in words
+	pub fn capacity(&self) -> usize {
+		self.size
+	}
+
+	pub fn get(&self, index: usize) -> usize {
+		assert!(index < self.capacity());
+		unsafe { ptr::read_volatile(self.mem.as_ptr::<usize>().add(index)) }
+	}
+
+	pub fn set(&self, index: usize, val: usize) {
+		assert!(index < self.capacity());
+		unsafe { ptr::write_volatile(self.mem.as_ptr::<usize>().add(index), val) }
+	}
+
+	// reads the header once, so userspace changing it while it is used can't make the values inconsistent
+	pub fn header(&self) -> MsgHeader {
+		let len = self.get(LEN_WORD).clamp(HEADER_LEN, self.capacity());
+		let flags = self.get(FLAGS_WORD);
+
+		MsgHeader {
+			len,
+			flags: MsgFlags::from_bits_truncate(flags),
+			cap_count: min(get_bits(flags, 32..64), len - HEADER_LEN),
+		}
+	}
+
+	pub fn set_header(&self, len: usize, flags: MsgFlags, cap_count: usize, reply: usize) {
+		self.set(LEN_WORD, len);
+		self.set(FLAGS_WORD, flags.bits() | (cap_count << 32));
+		self.set(REPLY_WORD, reply);
+	}
+
+	// copies the data words from the other message, which has the given header, into this message starting at index dst
+	// returns the number of data words copied
+	pub fn copy_data_from(&self, other: &Message, header: &MsgHeader, dst: usize) -> usize {
+		let src = header.data_start();
+		let count = min(header.data_len(), self.capacity().saturating_sub(dst));
+
+		for i in 0..count {
+			self.set(dst + i, other.get(src + i));
+		}
+
+		count
+	}
+}
+
+// number of data words in a message passed in registers
+pub const REG_MSG_LEN: usize = 8;
+
+// a message passed in registers, it cannot hold capabilities
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RegMsg {
+	// cid of the reply channel, or 0 if there is no reply channel
+	pub reply: usize,
+	pub words: [usize; REG_MSG_LEN],
+}
+
+// where a message is sent from or recieved into
+#[derive(Debug, Clone)]
+pub enum MsgBuf {
+	Mem(Arc<SharedMem>),
+	// holds the words to send, recieved words are instead written to the recieving thread
+	Regs(RegMsg),
+	// used on channels with a msg_size of 0, which don't send messages
+	Empty,
+}
+
+impl MsgBuf {
+	// returns None if the buffer is memory that is not big enough to hold a message of up to msg_size words
+	fn message(&self, msg_size: usize) -> Option<Option<Message>> {
+		match self {
+			Self::Mem(mem) => Some(Some(Message::new(mem, msg_size)?)),
+			Self::Regs(_) | Self::Empty => Some(None),
+		}
+	}
+
+	// words held by a register message, or all 0 if this is not a register message
+	fn reg_words(&self) -> [usize; REG_MSG_LEN] {
+		match self {
+			Self::Regs(regs) => regs.words,
+			_ => [0; REG_MSG_LEN],
+		}
+	}
+}
+
+// copies a message between two message buffers, limited by the channel's message properties
+// if the reciever uses registers, the recieved message is written to recv_thread
+// if reply is Some, a capability to the reply channel is given to the recieving process
+// badge is the badge of the capability the message was sent with, and is given to recv_thread,
+// which returns it from synchronous recieves that don't recieve into registers
+// returns the error code that both the sending and recieving thread should return
+pub fn transfer(
+	send_buf: &MsgBuf,
+	send_proc: &Process,
+	recv_buf: &MsgBuf,
+	recv_thread: &Thread,
+	recv_proc: &Process,
+	props: MsgProps,
+	reply: Option<&Arc<Channel>>,
+	badge: usize,
+) -> SysErr {
+	recv_thread.set_ipc_badge(badge);
+
+	if props.msg_size == 0 {
+		return SysErr::Ok;
+	}
+
+	let send_msg = send_buf.message(props.msg_size);
+	let recv_msg = recv_buf.message(props.msg_size);
+	let (send_msg, recv_msg) = match (send_msg, recv_msg) {
+		(Some(send_msg), Some(recv_msg)) => (send_msg, recv_msg),
+		_ => return SysErr::InvlArgs,
+	};
+
+	let reply_cid = match reply {
+		Some(reply) => recv_proc.channels().insert(Capability::new(reply.clone(), CapFlags::PROD)).into(),
+		None => 0,
+	};
+
+	// register messages are also limited by the channel's message size
+	let reg_len = min(REG_MSG_LEN, props.msg_size - HEADER_LEN);
+
+	match (send_msg, recv_msg) {
+		(Some(send_msg), Some(recv_msg)) => {
+			transfer_mem(send_msg, send_proc, recv_msg, recv_proc, props.max_caps, reply_cid)
+		},
+		// capabilities are not sent to a register reciever, only the first REG_MSG_LEN data words
+		(Some(send_msg), None) => {
+			let mut words = [0; REG_MSG_LEN];
+			let header = send_msg.header();
+			let src = header.data_start();
+			for i in 0..min(reg_len, header.data_len()) {
+				words[i] = send_msg.get(src + i);
+			}
+
+			recv_thread.set_ipc_regs(RegMsg {
+				reply: reply_cid,
+				words,
+			});
+		},
+		(None, Some(recv_msg)) => {
+			let words = send_buf.reg_words();
+
+			let count = min(reg_len, recv_msg.capacity() - HEADER_LEN);
+			for i in 0..count {
+				recv_msg.set(HEADER_LEN + i, words[i]);
+			}
+
+			let flags = if reply.is_some() {
+				MsgFlags::REPLY
+			} else {
+				MsgFlags::empty()
+			};
+			recv_msg.set_header(HEADER_LEN + count, flags, 0, reply_cid);
+		},
+		(None, None) => {
+			let mut words = send_buf.reg_words();
+			words[reg_len..].fill(0);
+
+			recv_thread.set_ipc_regs(RegMsg {
+				reply: reply_cid,
+				words,
+			});
+		},
+	}
+
+	SysErr::Ok
+}
+
+// copies a message between two message buffers in memory, and clones up to max_caps of the capabilities
+// listed in the message from the sending process into the recieving process
+fn transfer_mem(
+	send_msg: Message,
+	send_proc: &Process,
+	recv_msg: Message,
+	recv_proc: &Process,
+	max_caps: usize,
+	reply_cid: usize,
+) {
+	let header = send_msg.header();
+	let send_flags = header.flags;
+	let map = send_flags.contains(MsgFlags::MAP);
+
+	// when mapping, each capability also needs a word for its address
+	let cap_space = recv_msg.capacity() - HEADER_LEN;
+	let cap_space = if map { cap_space / 2 } else { cap_space };
+	let cap_count = min(min(header.cap_count, max_caps), cap_space);
+
+	let mut sent_caps = 0;
+	let mut map_addrs = Vec::new();
+
+	for i in 0..cap_count {
+		let cid = CapId::from(send_msg.get(HEADER_LEN + i));
+		let new_cid = if send_flags.contains(MsgFlags::MOVE) {
+			send_proc.move_cap_to(cid, recv_proc)
+		} else {
+			send_proc.clone_cap_to(cid, recv_proc)
+		};
+
+		// capabilities that don't exist are ignored, and are not counted in the recieved cap_count
+		if let Some(new_cid) = new_cid {
+			recv_msg.set(HEADER_LEN + sent_caps, new_cid.into());
+			sent_caps += 1;
+
+			if map {
+				let addr = match new_cid.cap_object_type() {
+					Some(CapObjectType::SMem) => recv_proc
+						.smem()
+						.map(recv_proc, new_cid, None, false)
+						.map_or(0, |vrange| vrange.as_usize()),
+					_ => 0,
+				};
+				map_addrs.push(addr);
+			}
+		}
+	}
+
+	for (i, addr) in map_addrs.iter().enumerate() {
+		recv_msg.set(HEADER_LEN + sent_caps + i, *addr);
+	}
+
+	let data_start = HEADER_LEN + sent_caps + map_addrs.len();
+	let data_len = recv_msg.copy_data_from(&send_msg, &header, data_start);
+	let flags = send_flags & MsgFlags::MAP;
+
+	let flags = if reply_cid != 0 {
+		flags | MsgFlags::REPLY
+	} else {
+		flags
+	};
+
+	recv_msg.set_header(data_start + data_len, flags, sent_caps, reply_cid);
+}
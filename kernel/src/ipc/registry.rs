@@ -0,0 +1,205 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+
+use spin::Mutex;
+use sys_consts::options::RegOptions;
+
+use crate::uses::*;
+use crate::sched::{block, tlist, Pid, Process, ThreadState};
+use crate::upriv::PrivLevel;
+use super::channel::Channel;
+
+// channels registered by servers, so clients can find them by name
+static registry: Mutex<BTreeMap<String, Name>> = Mutex::new(BTreeMap::new());
+
+// a channel registered under a name by a process
+#[derive(Debug)]
+struct RegEntry {
+	pid: Pid,
+	process: Weak<Process>,
+	// the registry does not keep the channel alive, so it is not resurrected after all its capabilities are destroyed
+	channel: Weak<Channel>,
+	options: RegOptions,
+}
+
+impl RegEntry {
+	// returns false if the process or the channel has been destroyed
+	fn is_alive(&self) -> bool {
+		self.process.upgrade().map_or(false, |process| process.is_alive()) && self.channel().is_some()
+	}
+
+	fn channel(&self) -> Option<Arc<Channel>> {
+		self.channel.upgrade().filter(|channel| channel.is_alive())
+	}
+
+	fn is_default(&self) -> bool {
+		self.options.contains(RegOptions::DEFAULT)
+	}
+
+	// returns true if process is allowed to connect to this channel
+	fn can_connect(&self, process: &Process) -> bool {
+		let uid = match self.process.upgrade() {
+			Some(owner) => owner.uid(),
+			None => return false,
+		};
+
+		self.options.contains(RegOptions::PUBLIC)
+			|| process.uid() <= PrivLevel::SuperUser
+			|| process.pid() == self.pid
+			|| (self.options.contains(RegOptions::GROUP) && process.uid() == uid)
+	}
+}
+
+// safety: only call in atomic_process which is called by scheduler
+pub unsafe fn force_unlock() {
+	registry.force_unlock();
+}
+
+// wakes threads waiting to register a default channel
+// waiters are not tracked per name, so all of them are woken and check their name again
+fn wake_default_waiters() {
+	tlist.state_move(ThreadState::RegWait, ThreadState::Ready, usize::MAX);
+}
+
+#[derive(Debug)]
+struct Name {
+	entries: Vec<RegEntry>,
+}
+
+impl Name {
+	fn new() -> Self {
+		Name {
+			entries: Vec::new(),
+		}
+	}
+
+	fn default(&self) -> Option<&RegEntry> {
+		self.entries.iter().find(|entry| entry.is_default())
+	}
+
+	fn get(&self, pid: Pid) -> Option<&RegEntry> {
+		self.entries.iter().find(|entry| entry.pid == pid)
+	}
+
+	// removes registrations for processes that have exited, or channels that have been destroyed
+	fn prune(&mut self) {
+		let had_default = self.default().is_some();
+		self.entries.retain(|entry| entry.is_alive());
+
+		if had_default && self.default().is_none() {
+			wake_default_waiters();
+		}
+	}
+
+	fn remove(&mut self, pid: Pid) -> bool {
+		let index = match self.entries.iter().position(|entry| entry.pid == pid) {
+			Some(index) => index,
+			None => return false,
+		};
+
+		if self.entries.remove(index).is_default() {
+			wake_default_waiters();
+		}
+		true
+	}
+}
+
+// registers channel under name for process
+// default registrations are always global, and if another process already has the default registration,
+// this will wait for it to be removed if the block option is set
+pub fn register(
+	name: String,
+	process: &Arc<Process>,
+	channel: Arc<Channel>,
+	options: RegOptions,
+) -> SysErr {
+	let options = if options.contains(RegOptions::DEFAULT) {
+		options | RegOptions::GLOBAL
+	} else {
+		options
+	};
+
+	loop {
+		let mut names = registry.lock();
+		let entry = names.entry(name.clone()).or_insert_with(Name::new);
+		entry.prune();
+
+		if entry.get(process.pid()).is_some() {
+			return SysErr::InvlOp;
+		}
+
+		if options.contains(RegOptions::DEFAULT) && entry.default().is_some() {
+			if !options.contains(RegOptions::BLOCK) {
+				return SysErr::InvlOp;
+			}
+
+			// the registry lock is released by the scheduler after this thread is on the wait list,
+			// so the default registration can't be removed before this thread can be woken
+			block(ThreadState::RegWait);
+			core::mem::forget(names);
+			continue;
+		}
+
+		entry.entries.push(RegEntry {
+			pid: process.pid(),
+			process: Arc::downgrade(process),
+			channel: Arc::downgrade(&channel),
+			options,
+		});
+		return SysErr::Ok;
+	}
+}
+
+// removes the channel process registered under name
+pub fn unregister(name: &str, pid: Pid) -> SysErr {
+	let mut names = registry.lock();
+	let removed = names.get_mut(name).map_or(false, |entry| entry.remove(pid));
+	remove_if_empty(&mut names, name);
+
+	if removed {
+		SysErr::Ok
+	} else {
+		SysErr::InvlArgs
+	}
+}
+
+// gets the channel registered under name by the process with pid,
+// or the default or first global registration if pid is None
+pub fn connect(name: &str, process: &Process, pid: Option<Pid>) -> Result<Arc<Channel>, SysErr> {
+	let mut names = registry.lock();
+	let entry = names.get_mut(name).ok_or(SysErr::InvlArgs)?;
+	entry.prune();
+	if entry.entries.is_empty() {
+		names.remove(name);
+		return Err(SysErr::InvlArgs);
+	}
+
+	let global = |reg: &&RegEntry| reg.options.contains(RegOptions::GLOBAL);
+	let reg = match pid {
+		Some(pid) => entry.get(pid),
+		None => entry.default().or_else(|| entry.entries.iter().find(global)),
+	}
+	.ok_or(SysErr::InvlArgs)?;
+
+	if !reg.can_connect(process) {
+		return Err(SysErr::InvlPerm);
+	}
+
+	// the channel could have been destroyed since it was pruned
+	reg.channel().ok_or(SysErr::InvlArgs)
+}
+
+// removes registrations of processes that have exited and channels that have been destroyed,
+// called when a process exits so threads waiting for its default registrations are woken
+pub fn prune() {
+	registry.lock().retain(|_, entry| {
+		entry.prune();
+		!entry.entries.is_empty()
+	});
+}
+
+fn remove_if_empty(names: &mut BTreeMap<String, Name>, name: &str) {
+	if names.get(name).map_or(false, |entry| entry.entries.is_empty()) {
+		names.remove(name);
+	}
+}
@@ -0,0 +1,337 @@
+use alloc::sync::Arc;
+
+use sys_consts::options::{ChannelOptions, ConnectOptions, IpcOptions, RegOptions};
+
+use crate::uses::*;
+use crate::sysret;
+use crate::syscall::SyscallVals;
+use crate::syscall::udata::{UserString, verify_ucanonical};
+use crate::cap::{CapId, CapFlags, Capability};
+use crate::sched::{proc_c, thread_c, Pid};
+use super::channel::{AsyncSignal, Channel};
+use super::registry;
+use super::msg::{Message, MsgBuf, MsgProps, RegMsg, HEADER_LEN};
+
+// gets the message buffer referenced by msg_id, checking that it has the required permissions,
+// and that it can send messages with props if msg_flags has READ, and recieve them if msg_flags has WRITE
+// no messages are sent on channels with a msg_size of 0, so msg_id is ignored and an empty buffer is returned
+fn mem_buf(msg_id: usize, msg_flags: CapFlags, props: MsgProps) -> Result<MsgBuf, SysErr> {
+	if props.msg_size == 0 {
+		return Ok(MsgBuf::Empty);
+	}
+
+	let mem = proc_c().smem().fetch(CapId::from(msg_id), msg_flags)?;
+	let msg = Message::new(&mem, props.msg_size).ok_or(SysErr::InvlArgs)?;
+
+	// a recieving buffer must be able to hold the biggest message that can be sent over the channel
+	if msg_flags.contains(CapFlags::WRITE) && msg.capacity() < props.msg_size {
+		return Err(SysErr::InvlArgs);
+	}
+
+	if msg_flags.contains(CapFlags::READ) && msg.header().cap_count > props.max_caps {
+		return Err(SysErr::InvlArgs);
+	}
+
+	Ok(MsgBuf::Mem(mem))
+}
+
+// gets the channel, the badge of the channel capability, and message buffer used by a channel syscall,
+// checking that they have the required permissions
+// if the regs option is set, the message is passed in registers a3-a10 instead, and msg_id is ignored
+fn channel_args(
+	vals: &SyscallVals,
+	channel_id: usize,
+	channel_flags: CapFlags,
+	msg_id: usize,
+	msg_flags: CapFlags,
+) -> Result<(Arc<Channel>, usize, MsgBuf), SysErr> {
+	let (channel, badge) = proc_c().channels().fetch_badged(CapId::from(channel_id), channel_flags)?;
+	let props = channel.msg_props();
+
+	// clear the badge of any previously recieved message, so nothing stale is returned if no message is recieved
+	thread_c().set_ipc_badge(0);
+
+	let regs = IpcOptions::from_bits_truncate(vals.options).contains(IpcOptions::REGS);
+	let msg_buf = if regs && props.msg_size != 0 {
+		// clear any previously recieved message, so nothing stale is returned if no message is recieved
+		thread_c().set_ipc_regs(RegMsg::default());
+
+		MsgBuf::Regs(RegMsg {
+			reply: 0,
+			words: [vals.a3, vals.a4, vals.a5, vals.a6, vals.a7, vals.a8, vals.a9, vals.a10],
+		})
+	} else {
+		mem_buf(msg_id, msg_flags, props)?
+	};
+
+	Ok((channel, badge, msg_buf))
+}
+
+// gets the channel, the badge of the channel capability, and message buffer used by an asynchronous channel syscall,
+// which always use a message buffer
+fn async_channel_args(
+	channel_id: usize,
+	channel_flags: CapFlags,
+	msg_id: usize,
+	msg_flags: CapFlags,
+) -> Result<(Arc<Channel>, usize, MsgBuf), SysErr> {
+	let (channel, badge) = proc_c().channels().fetch_badged(CapId::from(channel_id), channel_flags)?;
+	let msg_buf = mem_buf(msg_id, msg_flags, channel.msg_props())?;
+	Ok((channel, badge, msg_buf))
+}
+
+// gets the event used by an asynchronous channel syscall, and asynchronously listens on it with handler
+// the returned signal sends the message buffer's cid to the event, and the badge of the recieved message if the event takes 2 arguments
+// only recieves can use an event that takes the badge
+// returns InvlArgs if handler is not a user address,
+// or InvlOp if the thread is already asynchronously listening on the event with a different handler
+fn async_signal(event_id: usize, handler: usize, msg_id: usize, recv: bool) -> Result<AsyncSignal, SysErr> {
+	if !verify_ucanonical(handler) {
+		return Err(SysErr::InvlArgs);
+	}
+
+	let event_cid = CapId::from(event_id);
+	let event = proc_c().events().fetch(event_cid, CapFlags::WRITE)?;
+
+	let max_argc = if recv { 2 } else { 1 };
+	if !(1..=max_argc).contains(&event.argc()) {
+		return Err(SysErr::InvlArgs);
+	}
+
+	match event.ensure_alisten(thread_c().tuid(), event_cid, handler) {
+		SysErr::Ok => (),
+		err => return Err(err),
+	}
+	Ok(AsyncSignal::new(event, CapId::from(msg_id)))
+}
+
+// sets the return values of a syscall that recieves a message
+// a message recieved in registers is returned with the reply cid in a2, and the message in a3-a10
+// otherwise, the badge of the recieved message is returned in a2
+fn recv_ret(vals: &mut SyscallVals, msg_buf: &MsgBuf, err: SysErr) {
+	vals.a1 = err.num();
+
+	if let MsgBuf::Regs(_) = msg_buf {
+		let msg = thread_c().ipc_regs();
+		vals.a2 = msg.reply;
+		vals.a3 = msg.words[0];
+		vals.a4 = msg.words[1];
+		vals.a5 = msg.words[2];
+		vals.a6 = msg.words[3];
+		vals.a7 = msg.words[4];
+		vals.a8 = msg.words[5];
+		vals.a9 = msg.words[6];
+		vals.a10 = msg.words[7];
+	} else {
+		vals.a2 = thread_c().ipc_badge();
+	}
+}
+
+pub extern "C" fn channel_new(vals: &mut SyscallVals) {
+	let props = MsgProps {
+		msg_size: vals.a2,
+		max_caps: vals.a3,
+	};
+
+	// a message must be able to hold at least the header and 1 word
+	if (1..=HEADER_LEN).contains(&props.msg_size) {
+		sysret!(vals, SysErr::InvlArgs.num(), 0);
+	}
+
+	let flags = CapFlags::from_bits_truncate(vals.options as usize);
+	let options = ChannelOptions::from_bits_truncate(vals.options);
+
+	let allocator = match proc_c().allocators().fetch(CapId::from(vals.a1), CapFlags::PROD) {
+		Ok(allocator) => allocator,
+		Err(err) => sysret!(vals, err.num(), 0),
+	};
+
+	let channel = match Channel::new(props, options.contains(ChannelOptions::SC_RESIST), flags, allocator) {
+		Ok(channel) => channel,
+		Err(err) => sysret!(vals, err.num(), 0),
+	};
+	let cid = proc_c().channels().insert(channel);
+	sysret!(vals, SysErr::Ok.num(), cid.into());
+}
+
+pub extern "C" fn channel_msg_props(vals: &mut SyscallVals) {
+	let channel = match proc_c().channels().fetch(CapId::from(vals.a1), CapFlags::READ) {
+		Ok(channel) => channel,
+		Err(err) => sysret!(vals, err.num(), 0, 0),
+	};
+
+	let props = channel.msg_props();
+	sysret!(vals, SysErr::Ok.num(), props.msg_size, props.max_caps);
+}
+
+pub extern "C" fn channel_send(vals: &mut SyscallVals) {
+	let (channel, badge, msg_buf) = match channel_args(vals, vals.a1, CapFlags::PROD, vals.a2, CapFlags::READ) {
+		Ok(args) => args,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	sysret!(vals, channel.send(msg_buf, badge).num());
+}
+
+pub extern "C" fn channel_recv(vals: &mut SyscallVals) {
+	let (channel, _, msg_buf) = match channel_args(vals, vals.a1, CapFlags::WRITE, vals.a2, CapFlags::WRITE) {
+		Ok(args) => args,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	let err = channel.recv(msg_buf.clone());
+	recv_ret(vals, &msg_buf, err);
+}
+
+pub extern "C" fn channel_nbsend(vals: &mut SyscallVals) {
+	let (channel, badge, msg_buf) = match channel_args(vals, vals.a1, CapFlags::PROD, vals.a2, CapFlags::READ) {
+		Ok(args) => args,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	sysret!(vals, channel.nbsend(msg_buf, badge).num());
+}
+
+pub extern "C" fn channel_nbrecv(vals: &mut SyscallVals) {
+	let (channel, _, msg_buf) = match channel_args(vals, vals.a1, CapFlags::WRITE, vals.a2, CapFlags::WRITE) {
+		Ok(args) => args,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	let err = channel.nbrecv(msg_buf.clone());
+	recv_ret(vals, &msg_buf, err);
+}
+
+pub extern "C" fn channel_asend(vals: &mut SyscallVals) {
+	let (channel, badge, msg_buf) = match async_channel_args(vals.a1, CapFlags::PROD, vals.a2, CapFlags::READ) {
+		Ok(args) => args,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	let signal = match async_signal(vals.a3, vals.a4, vals.a2, false) {
+		Ok(signal) => signal,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	sysret!(vals, channel.asend(msg_buf, badge, signal).num());
+}
+
+pub extern "C" fn channel_arecv(vals: &mut SyscallVals) {
+	let (channel, _, msg_buf) = match async_channel_args(vals.a1, CapFlags::WRITE, vals.a2, CapFlags::WRITE) {
+		Ok(args) => args,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	let signal = match async_signal(vals.a3, vals.a4, vals.a2, true) {
+		Ok(signal) => signal,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	sysret!(vals, channel.arecv(msg_buf, signal).num());
+}
+
+pub extern "C" fn channel_reply_recv(vals: &mut SyscallVals) {
+	let msg_flags = CapFlags::READ | CapFlags::WRITE;
+	let (reply, _, msg_buf) = match channel_args(vals, vals.a1, CapFlags::PROD, vals.a3, msg_flags) {
+		Ok(args) => args,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	let recv_channel = match proc_c().channels().fetch(CapId::from(vals.a2), CapFlags::WRITE) {
+		Ok(channel) => channel,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	if reply.msg_props() != recv_channel.msg_props() {
+		sysret!(vals, SysErr::InvlArgs.num());
+	}
+
+	// the caller is not waiting anymore if reply fails, so there is nothing to report
+	reply.reply(msg_buf.clone());
+	proc_c().channels().remove(CapId::from(vals.a1));
+
+	let err = recv_channel.recv(msg_buf.clone());
+	recv_ret(vals, &msg_buf, err);
+}
+
+pub extern "C" fn channel_call(vals: &mut SyscallVals) {
+	let msg_flags = CapFlags::READ | CapFlags::WRITE;
+	let (channel, badge, msg_buf) = match channel_args(vals, vals.a1, CapFlags::PROD, vals.a2, msg_flags) {
+		Ok(args) => args,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	// the reply capability is sent in the message, so calls need a channel that sends messages
+	if channel.msg_props().msg_size == 0 {
+		sysret!(vals, SysErr::InvlArgs.num());
+	}
+
+	let err = channel.call(msg_buf.clone(), badge);
+	recv_ret(vals, &msg_buf, err);
+}
+
+pub extern "C" fn channel_acall(vals: &mut SyscallVals) {
+	let msg_flags = CapFlags::READ | CapFlags::WRITE;
+	let (channel, badge, msg_buf) = match async_channel_args(vals.a1, CapFlags::PROD, vals.a2, msg_flags) {
+		Ok(args) => args,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	// the reply capability is sent in the message, so calls need a channel that sends messages
+	if channel.msg_props().msg_size == 0 {
+		sysret!(vals, SysErr::InvlArgs.num());
+	}
+
+	let signal = match async_signal(vals.a3, vals.a4, vals.a2, false) {
+		Ok(signal) => signal,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	sysret!(vals, channel.acall(msg_buf, badge, signal).num());
+}
+
+pub extern "C" fn reg(vals: &mut SyscallVals) {
+	let options = RegOptions::from_bits_truncate(vals.options);
+
+	let name = match UserString::from_parts(vals.a1 as *const u8, vals.a2).try_fetch() {
+		Some(name) => name,
+		None => sysret!(vals, SysErr::InvlPtr.num()),
+	};
+
+	let process = proc_c();
+
+	if options.contains(RegOptions::REMOVE) {
+		sysret!(vals, registry::unregister(&name, process.pid()).num());
+	}
+
+	let channel = match process.channels().fetch(CapId::from(vals.a3), CapFlags::PROD) {
+		Ok(channel) => channel,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	sysret!(vals, registry::register(name, &process, channel, options).num());
+}
+
+pub extern "C" fn connect(vals: &mut SyscallVals) {
+	let options = ConnectOptions::from_bits_truncate(vals.options);
+
+	let name = match UserString::from_parts(vals.a1 as *const u8, vals.a2).try_fetch() {
+		Some(name) => name,
+		None => sysret!(vals, SysErr::InvlPtr.num(), 0),
+	};
+
+	let pid = if options.contains(ConnectOptions::PID) {
+		Some(Pid::from(vals.a3))
+	} else {
+		None
+	};
+
+	let process = proc_c();
+	let channel = match registry::connect(&name, &process, pid) {
+		Ok(channel) => channel,
+		Err(err) => sysret!(vals, err.num(), 0),
+	};
+
+	let cid = process.channels().insert(Capability::new(channel, CapFlags::PROD));
+	sysret!(vals, SysErr::Ok.num(), cid.into());
+}
@@ -26,4 +26,8 @@ impl CapObject for Key {
 
 	fn inc_ref(&self) {}
 	fn dec_ref(&self) {}
+
+	fn info(&self) -> [usize; 2] {
+		[self.id(), 0]
+	}
 }
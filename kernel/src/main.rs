@@ -33,6 +33,7 @@ mod time;
 mod util;
 
 mod cap;
+mod event;
 mod config;
 mod consts;
 mod gdt;
@@ -56,6 +57,7 @@ use acpi::SdtType;
 use mb2::BootInfo;
 use arch::x64::*;
 use sched::*;
+use sched::except::{is_user, user_exception};
 use int::*;
 use int::apic::lapic::Ipi;
 use consts::AP_CODE_START;
@@ -94,14 +96,67 @@ fn double_fault(_: &mut Registers, _: u64) -> bool
 	false
 }
 
-fn gp_exception(_: &mut Registers, _: u64) -> bool
+fn gp_exception(regs: &mut Registers, code: u64) -> bool
 {
+	if is_user(regs) {
+		return user_exception(idt::EXC_GENERAL_PROTECTION_FAULT, regs, code, 0);
+	}
+
 	println!("general protection exception");
 	false
 }
 
+// generates a handler for an exception that is caused by the code that was running
+// exceptions caused by userspace are sent to the process, and exceptions caused by the kernel panic
+macro_rules! fault_handler {
+	($name:ident, $vec:expr, $desc:literal) => {
+		fn $name(regs: &mut Registers, code: u64) -> bool
+		{
+			if is_user(regs) {
+				return user_exception($vec, regs, code, 0);
+			}
+
+			panic!(concat!($desc, " exception in kernel\nregisters:\n{:x?}"), regs);
+		}
+	};
+}
+
+fault_handler!(divide_error, idt::EXC_DIVIDE_BY_ZERO, "divide error");
+fault_handler!(overflow, idt::EXC_OVERFLOW, "overflow");
+fault_handler!(bound_range, idt::EXC_BOUND_RANGE_EXCEED, "bound range exceeded");
+fault_handler!(invalid_opcode, idt::EXC_INVALID_OPCODE, "invalid opcode");
+fault_handler!(device_unavailable, idt::EXC_DEVICE_UNAVAILABLE, "device not available");
+fault_handler!(segment_not_present, idt::EXC_SEGMENT_NOT_PRESENT, "segment not present");
+fault_handler!(stack_segment, idt::EXC_STACK_SEGMENT_FULL, "stack segment fault");
+fault_handler!(x87_floating_point, idt::EXC_X87_FLOATING_POINT, "x87 floating point");
+fault_handler!(alignment_check, idt::EXC_ALIGNMENT_CHECK, "alignment check");
+fault_handler!(simd_floating_point, idt::EXC_SIMD_FLOATING_POINT, "simd floating point");
+
+// registers handlers for exceptions that userspace can cause, must be called on every cpu
+// they are last handlers, because user_exception switches to another thread by returning new registers
+fn register_fault_handlers() -> Result<(), util::Err>
+{
+	Handler::Last(page_fault).register(idt::EXC_PAGE_FAULT)?;
+	Handler::Last(gp_exception).register(idt::EXC_GENERAL_PROTECTION_FAULT)?;
+	Handler::Last(divide_error).register(idt::EXC_DIVIDE_BY_ZERO)?;
+	Handler::Last(overflow).register(idt::EXC_OVERFLOW)?;
+	Handler::Last(bound_range).register(idt::EXC_BOUND_RANGE_EXCEED)?;
+	Handler::Last(invalid_opcode).register(idt::EXC_INVALID_OPCODE)?;
+	Handler::Last(device_unavailable).register(idt::EXC_DEVICE_UNAVAILABLE)?;
+	Handler::Last(segment_not_present).register(idt::EXC_SEGMENT_NOT_PRESENT)?;
+	Handler::Last(stack_segment).register(idt::EXC_STACK_SEGMENT_FULL)?;
+	Handler::Last(x87_floating_point).register(idt::EXC_X87_FLOATING_POINT)?;
+	Handler::Last(alignment_check).register(idt::EXC_ALIGNMENT_CHECK)?;
+	Handler::Last(simd_floating_point).register(idt::EXC_SIMD_FLOATING_POINT)?;
+	Ok(())
+}
+
 fn page_fault(regs: &mut Registers, code: u64) -> bool
 {
+	if code & idt::PAGE_FAULT_USER != 0 {
+		return user_exception(idt::EXC_PAGE_FAULT, regs, code, get_cr2());
+	}
+
 	let ring = if code & idt::PAGE_FAULT_USER != 0 {
 		"user"
 	} else {
@@ -167,9 +222,8 @@ fn init(boot_info: &BootInfo) -> Result<(), util::Err>
 
 	idt::init();
 
-	Handler::First(page_fault).register(idt::EXC_PAGE_FAULT)?;
+	register_fault_handlers()?;
 	Handler::Normal(double_fault).register(idt::EXC_DOUBLE_FAULT)?;
-	Handler::Normal(gp_exception).register(idt::EXC_GENERAL_PROTECTION_FAULT)?;
 	Handler::First(ipi_panic).register(idt::IPI_PANIC)?;
 
 	syscall::init();
@@ -199,6 +253,8 @@ fn init(boot_info: &BootInfo) -> Result<(), util::Err>
 		time::pit::init()?;
 	}
 
+	int::interrupt::init()?;
+
 	Ok(())
 }
 
@@ -209,9 +265,8 @@ fn ap_init(proc_id: usize, stack_top: usize) -> Result<(), util::Err> {
 
 	idt::init();
 
-	Handler::First(page_fault).register(idt::EXC_PAGE_FAULT)?;
+	register_fault_handlers()?;
 	Handler::Normal(double_fault).register(idt::EXC_DOUBLE_FAULT)?;
-	Handler::Normal(gp_exception).register(idt::EXC_GENERAL_PROTECTION_FAULT)?;
 	Handler::First(ipi_panic).register(idt::IPI_PANIC)?;
 
 	syscall::init();
@@ -243,13 +298,8 @@ pub extern "C" fn _start(boot_info_addr: usize) -> !
 
 	sti();
 
-	/*Process::from_elf(
-		boot_info.initrd,
-		PrivLevel::new(IOPRIV_UID),
-		"early-init".to_string(),
-		"initrd;/early-init".to_string(),
-	)
-	.unwrap();*/
+	// early-init is the only process given the root allocator, spawner, and int allocator by the kernel
+	Process::early_init(boot_info.initrd).expect("failed to load early-init");
 
 	test();
 
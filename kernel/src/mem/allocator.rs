@@ -0,0 +1,66 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::sync::Arc;
+
+use crate::uses::*;
+use crate::cap::{CapFlags, CapObject, CapObjectType, Capability};
+
+lazy_static! {
+	// only given to early-init, which passes it down to other processes
+	pub static ref root_alloc: Arc<Allocator> = Arc::new(Allocator::new(usize::MAX));
+}
+
+// permission to allocate a limited number of pages of memory for other objects
+#[derive(Debug)]
+pub struct Allocator {
+	max_pages: usize,
+	used_pages: AtomicUsize,
+}
+
+impl Allocator {
+	fn new(max_pages: usize) -> Self {
+		Allocator {
+			max_pages,
+			used_pages: AtomicUsize::new(0),
+		}
+	}
+
+	pub fn root_cap() -> Capability<Self> {
+		Capability::new(root_alloc.clone(), CapFlags::READ | CapFlags::PROD | CapFlags::WRITE)
+	}
+
+	pub fn max_pages(&self) -> usize {
+		self.max_pages
+	}
+
+	pub fn used_pages(&self) -> usize {
+		self.used_pages.load(Ordering::Acquire)
+	}
+
+	// returns OutOfMem if charging the pages would use more than max_pages
+	pub fn charge(&self, pages: usize) -> Result<(), SysErr> {
+		self.used_pages
+			.fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+				used.checked_add(pages).filter(|used| *used <= self.max_pages)
+			})
+			.map(|_| ())
+			.or(Err(SysErr::OutOfMem))
+	}
+
+	// must only be called with pages that were previously charged
+	pub fn uncharge(&self, pages: usize) {
+		self.used_pages.fetch_sub(pages, Ordering::AcqRel);
+	}
+}
+
+impl CapObject for Allocator {
+	fn cap_object_type() -> CapObjectType {
+		CapObjectType::Allocator
+	}
+
+	fn inc_ref(&self) {}
+	fn dec_ref(&self) {}
+
+	fn info(&self) -> [usize; 2] {
+		[self.used_pages(), self.max_pages]
+	}
+}
@@ -10,6 +10,7 @@ use crate::syscall::udata;
 // unless otherwise stated, all lens in this module are in bytes, not pages
 // TODO: make traits or macros to reduce duplicated code on Phys and Virt versions of all these types
 
+pub mod allocator;
 pub mod error;
 pub mod phys_alloc;
 pub mod shared_mem;
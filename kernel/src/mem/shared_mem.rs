@@ -1,29 +1,83 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
 use alloc::sync::Arc;
 use alloc::collections::BTreeMap;
 
 use crate::uses::*;
 use crate::util::{Futex, FutexGuard};
 use crate::cap::{CapId, CapFlags, Capability, CapObject, CapObjectType, Map};
+use crate::sched::{proc_get, Pid};
 use super::*;
 use super::phys_alloc::{zm, Allocation};
+use super::allocator::Allocator;
 use super::virt_alloc::{AllocType, PageMappingFlags, VirtLayout, VirtLayoutElement};
 
 #[derive(Debug)]
 pub struct SharedMem {
 	mem: Allocation,
-	cap_data: Futex<BTreeMap<CapId, VirtRange>>,
+	// allocator the memory is charged to
+	allocator: Arc<Allocator>,
+	cap_data: Futex<BTreeMap<(Pid, CapId), VirtRange>>,
+	// number of strong capabilities referencing this memory
+	cap_refs: AtomicUsize,
 }
 
 impl SharedMem
 {
-	pub fn new(size: usize, flags: CapFlags) -> Option<Capability<Self>>
+	// returns OutOfMem if there is not enough physical memory, or if the pages can't be charged to allocator
+	pub fn new(size: usize, flags: CapFlags, allocator: Arc<Allocator>) -> Result<Capability<Self>, SysErr>
 	{
-		let allocation = zm.alloc(size)?;
+		let allocation = zm.alloc(size).ok_or(SysErr::OutOfMem)?;
+		if let Err(err) = allocator.charge(allocation.len() / PAGE_SIZE) {
+			unsafe {
+				zm.dealloc(allocation);
+			}
+			return Err(err);
+		}
+
 		let arc = Arc::new(SharedMem {
 			mem: allocation,
+			allocator,
 			cap_data: Futex::new(BTreeMap::new()),
+			cap_refs: AtomicUsize::new(0),
 		});
-		Some(Capability::new(arc, flags))
+		Ok(Capability::new(arc, flags))
+	}
+
+	// size in bytes
+	pub fn size(&self) -> usize
+	{
+		self.mem.len()
+	}
+
+	// NOTE: userspace can modify this memory at any time, so anything read from it should not be trusted
+	pub fn as_ptr<T>(&self) -> *mut T
+	{
+		self.mem.as_usize() as *mut T
+	}
+
+	// unmaps the memory from every process it is still mapped in
+	// called when the last strong capability is destroyed, so no capability is left that could unmap it
+	fn unmap_all(&self) {
+		let cap_data = core::mem::take(&mut *self.cap_data.lock());
+
+		for ((pid, _), virt_range) in cap_data {
+			// processes that are not in the process list have exited, so their address space is not used anymore
+			if let Some(process) = proc_get(pid) {
+				unsafe {
+					let _ = process.addr_space.unmap(virt_range, AllocType::Shared);
+				}
+			}
+		}
+	}
+}
+
+// the memory is only freed once every message buffer using it is also done with it
+impl Drop for SharedMem {
+	fn drop(&mut self) {
+		unsafe {
+			zm.dealloc(self.mem);
+		}
+		self.allocator.uncharge(self.mem.len() / PAGE_SIZE);
 	}
 }
 
@@ -32,12 +86,24 @@ impl CapObject for SharedMem {
 		CapObjectType::SMem
 	}
 
-	fn inc_ref(&self) {}
-	fn dec_ref(&self) {}
+	fn inc_ref(&self) {
+		self.cap_refs.fetch_add(1, Ordering::AcqRel);
+	}
+
+	fn dec_ref(&self) {
+		if self.cap_refs.fetch_sub(1, Ordering::AcqRel) == 1 {
+			self.unmap_all();
+		}
+	}
+
+	// size in pages
+	fn info(&self) -> [usize; 2] {
+		[self.size() / PAGE_SIZE, 0]
+	}
 }
 
 impl Map for SharedMem {
-	type Lock<'a> = FutexGuard<'a, BTreeMap<CapId, VirtRange>>;
+	type Lock<'a> = FutexGuard<'a, BTreeMap<(Pid, CapId), VirtRange>>;
 
 	fn virt_layout(&self, flags: CapFlags) -> VirtLayout {
 		let elem = VirtLayoutElement::from_range(
@@ -51,16 +117,16 @@ impl Map for SharedMem {
 		AllocType::Shared
 	}
 
-	fn cap_map_data(&self, id: CapId) -> (Option<VirtRange>, Self::Lock<'_>) {
+	fn cap_map_data(&self, pid: Pid, id: CapId) -> (Option<VirtRange>, Self::Lock<'_>) {
 		let lock = self.cap_data.lock();
-		let out = lock.get(&id).map(|vr| *vr);
+		let out = lock.get(&(pid, id)).map(|vr| *vr);
 		(out, lock)
 	}
 
-	fn set_cap_map_data(&self, id: CapId, data: Option<VirtRange>, mut lock: Self::Lock<'_>) {
+	fn set_cap_map_data(&self, pid: Pid, id: CapId, data: Option<VirtRange>, mut lock: Self::Lock<'_>) {
 		match data {
-			Some(virt_range) => lock.insert(id, virt_range),
-			None => lock.remove(&id),
+			Some(virt_range) => lock.insert((pid, id), virt_range),
+			None => lock.remove(&(pid, id)),
 		};
 	}
 }
@@ -1,7 +1,7 @@
 use sys_consts::options::*;
 
 use crate::uses::*;
-use crate::cap::CapFlags;
+use crate::cap::{CapId, CapFlags};
 use crate::sysret;
 use crate::syscall::{SysErr, SyscallVals};
 use super::{VirtRange, PAGE_SIZE};
@@ -10,6 +10,7 @@ use super::virt_alloc::{AllocType, PageMappingFlags, VirtLayout, VirtLayoutEleme
 use super::shared_mem::*;
 use super::error::MemErr;
 use crate::sched::proc_c;
+use crate::consts::KERNEL_VMA;
 
 const READ: u32 = 1;
 const WRITE: u32 = 1 << 1;
@@ -170,16 +171,91 @@ pub extern "C" fn mprotect(vals: &mut SyscallVals) {
 	todo!();
 }
 
-pub extern "C" fn smem_new(vals: &mut SyscallVals)
+// the memory is charged to the allocator in a1
+// a mapping of the memory is readable with cap_read, writable with cap_write, and executable with cap_prod
+pub extern "C" fn mem_new(vals: &mut SyscallVals)
 {
-	let size = vals.a1 * PAGE_SIZE;
-	let options = CapFlags::from_bits_truncate(vals.options as usize);
-
-	let smem = match SharedMem::new(size, options) {
-		Some(smem) => smem,
+	let size = match vals.a2.checked_mul(PAGE_SIZE) {
+		Some(size) => size,
 		None => sysret!(vals, SysErr::OutOfMem.num(), 0),
 	};
+	let flags = CapFlags::from_bits_truncate(vals.options as usize);
+
+	let allocator = match proc_c().allocators().fetch(CapId::from(vals.a1), CapFlags::PROD) {
+		Ok(allocator) => allocator,
+		Err(err) => sysret!(vals, err.num(), 0),
+	};
+
+	let smem = match SharedMem::new(size, flags, allocator) {
+		Ok(smem) => smem,
+		Err(err) => sysret!(vals, err.num(), 0),
+	};
 
 	let cid = proc_c().smem().insert(smem);
 	sysret!(vals, SysErr::Ok.num(), cid.into());
 }
+
+pub extern "C" fn mem_size(vals: &mut SyscallVals)
+{
+	match proc_c().smem().fetch(CapId::from(vals.a1), CapFlags::READ) {
+		Ok(smem) => sysret!(vals, SysErr::Ok.num(), smem.size() / PAGE_SIZE, smem.size()),
+		Err(err) => sysret!(vals, err.num(), 0, 0),
+	}
+}
+
+// memory can only be mapped inside of the reserved zone by cap_map with map_force
+pub extern "C" fn mem_reserve(vals: &mut SyscallVals)
+{
+	let addr = vals.a2;
+	let pages = vals.a3;
+
+	let process = match proc_c().processes().fetch(CapId::from(vals.a1), CapFlags::WRITE) {
+		Ok(process) => process,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	let vaddr = match VirtAddr::try_new(addr as u64) {
+		Ok(vaddr) => vaddr,
+		Err(_) => sysret!(vals, SysErr::InvlVirtAddr.num()),
+	};
+
+	if !page_aligned(addr) {
+		sysret!(vals, SysErr::InvlAlign.num());
+	}
+
+	let size = match pages.checked_mul(PAGE_SIZE) {
+		Some(size) if size != 0 => size,
+		_ => sysret!(vals, SysErr::InvlArgs.num()),
+	};
+
+	// the reservation must end inside of userspace memory
+	match addr.checked_add(size) {
+		Some(end) if end <= *KERNEL_VMA => (),
+		_ => sysret!(vals, SysErr::InvlArgs.num()),
+	}
+
+	let virt_zone = VirtRange::new_unaligned(vaddr, size);
+
+	match process.addr_space.reserve(virt_zone) {
+		Ok(()) => sysret!(vals, SysErr::Ok.num()),
+		Err(err) => sysret!(vals, SysErr::from(err).num()),
+	}
+}
+
+pub extern "C" fn mem_unreserve(vals: &mut SyscallVals)
+{
+	let process = match proc_c().processes().fetch(CapId::from(vals.a1), CapFlags::WRITE) {
+		Ok(process) => process,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	let vaddr = match VirtAddr::try_new(vals.a2 as u64) {
+		Ok(vaddr) => vaddr,
+		Err(_) => sysret!(vals, SysErr::InvlVirtAddr.num()),
+	};
+
+	match process.addr_space.unreserve(vaddr) {
+		Ok(_) => sysret!(vals, SysErr::Ok.num()),
+		Err(err) => sysret!(vals, SysErr::from(err).num()),
+	}
+}
@@ -1,4 +1,5 @@
-use alloc::collections::BTreeMap;
+use core::cmp::max;
+use alloc::collections::{BTreeMap, BTreeSet};
 
 use bitflags::bitflags;
 
@@ -95,6 +96,7 @@ bitflags! {
 
 impl PageMappingFlags
 {
+	// read, write, and execute permissions come from cap_read, cap_write, and cap_prod respectively
 	pub fn from_cap_flags(flags: CapFlags) -> Self {
 		let mut out = PageMappingFlags::USER;
 		if flags.contains(CapFlags::READ) {
@@ -105,6 +107,10 @@ impl PageMappingFlags
 			out |= PageMappingFlags::WRITE;
 		}
 
+		if flags.contains(CapFlags::PROD) {
+			out |= PageMappingFlags::EXEC;
+		}
+
 		out
 	}
 
@@ -580,6 +586,12 @@ impl VirtLayout
 	}
 
 	// retuns none if virtlayout is completely empty (including dirty memory)
+	// returns true if every element of the layout is mapped with all of flags
+	pub fn has_flags(&self, flags: PageMappingFlags) -> bool
+	{
+		self.data.iter().all(|elem| elem.mapping_flags.contains(flags))
+	}
+
 	pub fn flags(&self) -> Option<PageMappingFlags>
 	{
 		if let Some(elem) = self.data.get(0) {
@@ -841,6 +853,9 @@ impl Iterator for PageMappingIterator
 pub struct VirtMapper<T: FrameAllocator + 'static>
 {
 	virt_map: Futex<BTreeMap<VirtRange, VirtLayout>>,
+	// memory can only be mapped in reserved zones with map_at_force
+	// reservations never overlap each other, and this is always locked after virt_map
+	reserved: Futex<BTreeSet<VirtRange>>,
 	cr3: Futex<PageTablePointer>,
 	// in order to avoid race condition
 	cr3_addr: usize,
@@ -862,6 +877,7 @@ impl<T: FrameAllocator> VirtMapper<T>
 		}
 		VirtMapper {
 			virt_map: Futex::new(BTreeMap::new()),
+			reserved: Futex::new(BTreeSet::new()),
 			cr3: Futex::new(pml4_table),
 			cr3_addr: pml4_table.0,
 			frame_allocer,
@@ -917,6 +933,14 @@ impl<T: FrameAllocator> VirtMapper<T>
 	}
 
 	pub fn range_map<F, U>(&self, virt_zone: VirtRange, f: F) -> Option<U>
+	where
+		F: FnOnce(&[u8]) -> Option<U>,
+	{
+		self.range_map_flags(virt_zone, PageMappingFlags::NONE, f)
+	}
+
+	// like range_map, but returns None if any of the memory in virt_zone is not mapped with all of flags
+	pub fn range_map_flags<F, U>(&self, virt_zone: VirtRange, flags: PageMappingFlags, f: F) -> Option<U>
 	where
 		F: FnOnce(&[u8]) -> Option<U>,
 	{
@@ -927,17 +951,15 @@ impl<T: FrameAllocator> VirtMapper<T>
 		let mut next_iter = btree.range(virt_zone..);
 		let next = next_iter.next();
 
-		if optac(prev, |p| p.0.full_contains_range(virt_zone))
-			|| optac(next, |n| n.0.full_contains_range(virt_zone))
+		if optac(prev, |p| p.0.full_contains_range(virt_zone) && p.1.has_flags(flags))
+			|| optac(next, |n| n.0.full_contains_range(virt_zone) && n.1.has_flags(flags))
 		{
 			return f(unsafe { virt_zone.as_slice() });
 		}
 
-		if prev.is_some() && next.is_some() {
-			let prev = prev.unwrap().0;
-			let next = next.unwrap().0;
+		if let (Some((prev, prev_layout)), Some((next, next_layout))) = (prev, next) {
 			if let Some(range) = prev.merge(*next) {
-				if range.full_contains_range(virt_zone) {
+				if range.full_contains_range(virt_zone) && prev_layout.has_flags(flags) && next_layout.has_flags(flags) {
 					return f(unsafe { virt_zone.as_slice() });
 				}
 			}
@@ -965,23 +987,27 @@ impl<T: FrameAllocator> VirtMapper<T>
 		btree.get(&virt_zone).is_some()
 	}
 
-	// find virt range of size size
+	// find virt range of size size that is not mapped or reserved
 	fn find_range(
 		btree: &FutexGuard<BTreeMap<VirtRange, VirtLayout>>,
+		reserved: &FutexGuard<BTreeSet<VirtRange>>,
 		size: usize,
 	) -> Option<VirtRange>
 	{
+		// memory mapped with map_at_force overlaps reserved zones, so zones may overlap each other here
+		let mut zones: Vec<VirtRange> = btree.keys().chain(reserved.iter()).copied().collect();
+		zones.sort_unstable();
+
 		// leave page at 0 empty so null pointers will page fault
 		let mut laddr = PAGE_SIZE;
 		let mut found = false;
 
-		for zone in btree.keys() {
-			let diff = zone.as_usize() - laddr;
-			if diff >= size {
+		for zone in zones {
+			if zone.as_usize() >= laddr && zone.as_usize() - laddr >= size {
 				found = true;
 				break;
 			}
-			laddr = zone.as_usize() + zone.size();
+			laddr = max(laddr, zone.end_usize());
 		}
 
 		if !found && (*MAX_MAP_ADDR - laddr < size) {
@@ -991,6 +1017,20 @@ impl<T: FrameAllocator> VirtMapper<T>
 		Some(VirtRange::new(VirtAddr::new(laddr as _), size))
 	}
 
+	// returns true if virt_zone overlaps any reserved zone
+	fn overlaps_reserved(
+		reserved: &FutexGuard<BTreeSet<VirtRange>>,
+		virt_zone: VirtRange,
+	) -> bool
+	{
+		// reserved zones don't overlap, so only the zones directly before and after virt_zone need to be checked
+		let prev = reserved.range(..virt_zone).next_back();
+		let next = reserved.range(virt_zone..).next();
+
+		optac(prev, |prev| prev.end_addr() > virt_zone.addr())
+			|| optac(next, |next| next.addr() < virt_zone.end_addr())
+	}
+
 	// get free space to left and right of virt_zone in bytes
 	// if there is interference to left and right of virt_zone, returns none
 	// pass with inclusive true to ensure virt_zone is not already inserted
@@ -1059,7 +1099,7 @@ impl<T: FrameAllocator> VirtMapper<T>
 
 		let mut btree = self.virt_map.lock();
 
-		let virt_zone = Self::find_range(&btree, size).ok_or(MemErr::InvlVirtMem(
+		let virt_zone = Self::find_range(&btree, &self.reserved.lock(), size).ok_or(MemErr::InvlVirtMem(
 			"not enough space in virtual memory space for allocation",
 		))?;
 
@@ -1072,10 +1112,23 @@ impl<T: FrameAllocator> VirtMapper<T>
 		Ok(virt_zone)
 	}
 
-	pub unsafe fn map_at(
+	// fails if virt_zone overlaps reserved memory
+	pub unsafe fn map_at(&self, phys_zones: VirtLayout, virt_zone: VirtRange) -> Result<VirtRange, MemErr>
+	{
+		self.map_at_inner(phys_zones, virt_zone, false)
+	}
+
+	// like map_at, but virt_zone can overlap reserved memory
+	pub unsafe fn map_at_force(&self, phys_zones: VirtLayout, virt_zone: VirtRange) -> Result<VirtRange, MemErr>
+	{
+		self.map_at_inner(phys_zones, virt_zone, true)
+	}
+
+	unsafe fn map_at_inner(
 		&self,
 		mut phys_zones: VirtLayout,
 		virt_zone: VirtRange,
+		force: bool,
 	) -> Result<VirtRange, MemErr>
 	{
 		let virt_zone = virt_zone.aligned();
@@ -1107,6 +1160,10 @@ impl<T: FrameAllocator> VirtMapper<T>
 			return Err(MemErr::InvlVirtMem("invalid virt zone passed to map_at"));
 		}
 
+		if !force && Self::overlaps_reserved(&self.reserved.lock(), virt_zone) {
+			return Err(MemErr::InvlVirtMem("virt zone passed to map_at overlaps reserved memory"));
+		}
+
 		let iter = PageMappingIterator::new(&phys_zones, &virt_zone);
 		self.map_internal(iter);
 		phys_zones.sync_mem();
@@ -1165,7 +1222,7 @@ impl<T: FrameAllocator> VirtMapper<T>
 
 			Ok(nrange)
 		} else {
-			let new_virt_zone = Self::find_range(&btree, new_size).ok_or_else(|| {
+			let new_virt_zone = Self::find_range(&btree, &self.reserved.lock(), new_size).ok_or_else(|| {
 				// make borrow checker happy
 				let virt_layout = btree.get_mut(&virt_zone).unwrap();
 				virt_layout.revert();
@@ -1254,6 +1311,40 @@ impl<T: FrameAllocator> VirtMapper<T>
 		}
 	}
 
+	// returns InvlVirtMem if virt_zone overlaps another reservation
+	// memory already mapped in virt_zone stays mapped
+	pub fn reserve(&self, virt_zone: VirtRange) -> Result<(), MemErr>
+	{
+		if virt_zone.size() == 0 {
+			return Err(MemErr::InvlArgs("tried to reserve zone of size zero"));
+		}
+
+		let mut reserved = self.reserved.lock();
+
+		if Self::overlaps_reserved(&reserved, virt_zone) {
+			return Err(MemErr::InvlVirtMem("virt zone passed to reserve overlaps another reservation"));
+		}
+
+		reserved.insert(virt_zone);
+		Ok(())
+	}
+
+	// removes the reservation starting at addr, and returns the zone that was reserved
+	pub fn unreserve(&self, addr: VirtAddr) -> Result<VirtRange, MemErr>
+	{
+		let mut reserved = self.reserved.lock();
+
+		let virt_zone = reserved
+			.range(VirtRange::new_unaligned(addr, 0)..)
+			.next()
+			.filter(|zone| zone.addr() == addr)
+			.copied()
+			.ok_or(MemErr::InvlPtr("no reservation starts at addr passed to unreserve"))?;
+
+		reserved.remove(&virt_zone);
+		Ok(virt_zone)
+	}
+
 	pub unsafe fn unmap(&self, virt_zone: VirtRange, atype: AllocType)
 		-> Result<VirtLayout, MemErr>
 	{
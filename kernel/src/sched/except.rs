@@ -0,0 +1,98 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+
+use crate::uses::*;
+use crate::arch::x64::{CPUPrivLevel, IntDisable};
+use super::{block, proc_all, suspend_current, thread_c, tlist, Registers, ThreadState};
+
+// set when a thread has stored an exception that has not been handled by except_dispatcher yet
+static PENDING: AtomicBool = AtomicBool::new(false);
+// held while setting PENDING and waking except_dispatcher, and while except_dispatcher checks PENDING and blocks
+// it is released by the scheduler in ThreadState::atomic_process once except_dispatcher is blocked, so no wakeup is lost
+static WAIT_LOCK: Mutex<()> = Mutex::new(());
+
+// an exception caused by userspace code, waiting to be sent to the process by except_dispatcher
+#[derive(Debug, Clone, Copy)]
+pub struct UserException {
+	pub vec: u8,
+	pub error_code: u64,
+	// only set for page faults
+	pub addr: usize,
+	pub rip: usize,
+}
+
+// safety: only call in atomic_process which is called by scheduler
+pub unsafe fn force_unlock() {
+	WAIT_LOCK.force_unlock();
+}
+
+// returns true if the registers were saved while the cpu was running userspace code
+pub fn is_user(regs: &Registers) -> bool {
+	regs.cs & 3 == CPUPrivLevel::Ring3.n() as u16
+}
+
+// handles an exception caused by userspace code, must be called from the last exception handler on the faulting thread
+// the faulting thread is suspended, and except_dispatcher later sends the process's exception event,
+// because sending events locks spinlocks and allocates memory, which can't be done in an exception handler
+// returns the value the exception handler should return
+pub fn user_exception(vec: u8, regs: &mut Registers, error_code: u64, addr: usize) -> bool {
+	thread_c().set_exception(UserException {
+		vec,
+		error_code,
+		addr,
+		rip: regs.rip,
+	});
+
+	let wait_lock = WAIT_LOCK.lock();
+	PENDING.store(true, Ordering::Release);
+	tlist.state_move(ThreadState::ExceptWait, ThreadState::Ready, 1);
+	drop(wait_lock);
+
+	suspend_current(regs)
+}
+
+// sends the exception events of threads that have been suspended by user_exception
+// if the process has an exception event bound, it is sent with the arguments
+// (exception vector, error code, faulting address, rip, tid)
+// otherwise, the process is terminated
+pub fn except_dispatcher() {
+	loop {
+		let int_disable = IntDisable::new();
+		let wait_lock = WAIT_LOCK.lock();
+
+		if !PENDING.swap(false, Ordering::AcqRel) {
+			// the scheduler unlocks wait_lock once this thread is blocked,
+			// so user_exception on another cpu can't try to wake this thread before it is blocked
+			block(ThreadState::ExceptWait);
+			core::mem::forget(wait_lock);
+			drop(int_disable);
+			continue;
+		}
+
+		drop(wait_lock);
+		drop(int_disable);
+
+		for process in proc_all() {
+			for (tid, exc) in process.take_exceptions() {
+				match process.except_event() {
+					Some(event) => {
+						let args = [exc.vec as usize, exc.error_code as usize, exc.addr, exc.rip, tid.into()];
+						event.send(&args);
+					},
+					None => {
+						eprintln!(
+							"process {} terminated by unhandled exception {} at rip {:x}",
+							process.name(),
+							exc.vec,
+							exc.rip
+						);
+
+						process.terminate();
+						break;
+					},
+				}
+			}
+		}
+	}
+}
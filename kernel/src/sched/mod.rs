@@ -9,6 +9,7 @@ use alloc::alloc::{Allocator, Global, Layout};
 
 use spin::Mutex;
 pub use process::{Pid, Process, SpawnMapFlags, SpawnStartState};
+pub use spawner::Spawner;
 use process::ipi_process_exit_handler;
 pub use thread::{Stack, Tid, ThreadRef, Thread, ThreadState, Tuid};
 pub use sync::{Fuid, FutexMap, KFutex};
@@ -25,6 +26,8 @@ use crate::mem::VirtRange;
 use crate::upriv::PrivLevel;
 use crate::consts::INIT_STACK;
 use crate::ipc::Ipcid;
+use crate::ipc::channel::Channel;
+use crate::event::Evid;
 
 // TODO: clean up code, it is kind of ugly
 // use new interrupt disabling machanism
@@ -41,13 +44,19 @@ use crate::ipc::Ipcid;
 // FIXME: there is a current race condition that occurs when using proc_c () function
 
 mod elf;
+pub mod except;
 mod process;
+mod spawner;
 mod sync;
 pub mod sys;
 mod thread;
 
 pub static tlist: ThreadListGuard = ThreadListGuard::new();
 static proc_list: Mutex<BTreeMap<Pid, Arc<Process>>> = Mutex::new(BTreeMap::new());
+// processes that will be terminated by the thread cleaner
+// threads can't terminate their own process, because they would never drop their reference to it,
+// and the process can't be dropped while its address space is loaded
+static term_list: Mutex<Vec<Arc<Process>>> = Mutex::new(Vec::new());
 
 // TODO: make this cpu local data
 // FIXME: this is a bad way to return registers, and won't be safe with smp
@@ -69,7 +78,7 @@ fn time_handler(regs: &mut Registers, _: u64) -> bool
 		// FIXME: ugly
 		for tpointer in unsafe { unbound_mut(time_list).iter() } {
 			let sleep_nsec = match tpointer.state() {
-				ThreadState::Sleep(nsec) => nsec,
+				ThreadState::Sleep(nsec) | ThreadState::EventSleep(_, nsec) => nsec,
 				_ => panic!("thread in sleep queue but state is not sleeping"),
 			};
 	
@@ -116,6 +125,22 @@ fn int_handler(regs: &mut Registers, _: u64) -> bool
 	schedule(regs, nsec_last, nsec_current)
 }
 
+// suspends the current thread and switches to another thread, the return value must be returned by the interrupt handler
+// unlike block, this can be called from an interrupt handler, since it doesn't issue another interrupt
+pub fn suspend_current(regs: &mut Registers) -> bool
+{
+	lock();
+
+	thread_c().set_state(ThreadState::Suspend);
+
+	let nsec_current = timer().nsec();
+	let mut cpd = cpud();
+	let nsec_last = cpd.last_switch_nsec;
+
+	cpd.last_switch_nsec = nsec_current;
+	schedule(regs, nsec_last, nsec_current)
+}
+
 fn int_sched()
 {
 	unsafe {
@@ -133,7 +158,13 @@ fn schedule(regs: &mut Registers, nsec_last: u64, nsec_current: u64) -> bool
 	let mut thread_list = tlist.lock();
 
 	let tpointer = loop {
-		match thread_list[ThreadState::Ready].pop_front() {
+		// a thread that was handed off to by the thread running on this cpu is run before any ready threads
+		let next = match thread_list[ThreadState::Handoff].pop_front() {
+			Some(t) => Some(t),
+			None => thread_list[ThreadState::Ready].pop_front(),
+		};
+
+		match next {
 			Some(t) => {
 				if !t.is_alive() {
 					t.set_state(ThreadState::Destroy);
@@ -211,7 +242,21 @@ fn thread_cleaner()
 	let mut list = LinkedList::new();
 
 	loop {
+		let terminated = core::mem::take(&mut *term_list.lock());
+		for process in terminated {
+			process.terminate();
+		}
+
 		let mut thread_list = tlist.lock();
+
+		// suspended threads are never woken, so they are destroyed here once their process exits
+		let slist = &mut thread_list[ThreadState::Suspend];
+		for tpointer in unsafe { unbound_mut(slist).iter() } {
+			if !tpointer.is_alive() {
+				Thread::move_to(tpointer, ThreadState::Destroy, &mut thread_list);
+			}
+		}
+
 		let dlist = &mut thread_list[ThreadState::Destroy];
 		for tpointer in unsafe { unbound_mut(dlist).iter() } {
 			if tpointer.ref_count() == 0 {
@@ -279,19 +324,27 @@ unsafe impl<T: Default + Copy> Send for TLTreeNode<T> {}
 libutil::impl_tree_node!(Tuid, TLTreeNode<Tuid>, parent, left, right, id, bf);
 libutil::impl_tree_node!(Ipcid, TLTreeNode<Ipcid>, parent, left, right, id, bf);
 libutil::impl_tree_node!(Fuid, TLTreeNode<Fuid>, parent, left, right, id, bf);
+libutil::impl_tree_node!(Evid, TLTreeNode<Evid>, parent, left, right, id, bf);
 
 #[derive(Debug)]
 pub struct ThreadList
 {
 	running: Vec<LinkedList<Thread>>,
+	handoff: Vec<LinkedList<Thread>>,
 	ready: LinkedList<Thread>,
 	idle: Vec<LinkedList<Thread>>,
 	destroy: LinkedList<Thread>,
 	sleep: LinkedList<Thread>,
+	awaiting: LinkedList<Thread>,
+	int_wait: LinkedList<Thread>,
+	except_wait: LinkedList<Thread>,
+	suspended: LinkedList<Thread>,
+	reg_wait: LinkedList<Thread>,
 	join: AvlTree<Tuid, TLTreeNode<Tuid>>,
 	wait: AvlTree<Tuid, TLTreeNode<Tuid>>,
 	conn_wait: AvlTree<Ipcid, TLTreeNode<Ipcid>>,
 	futex: AvlTree<Fuid, TLTreeNode<Fuid>>,
+	event_wait: AvlTree<Evid, TLTreeNode<Evid>>,
 }
 
 impl ThreadList
@@ -300,14 +353,21 @@ impl ThreadList
 	{
 		ThreadList {
 			running: Vec::new(),
+			handoff: Vec::new(),
 			ready: LinkedList::new(),
 			idle: Vec::new(),
 			destroy: LinkedList::new(),
 			sleep: LinkedList::new(),
+			awaiting: LinkedList::new(),
+			int_wait: LinkedList::new(),
+			except_wait: LinkedList::new(),
+			suspended: LinkedList::new(),
+			reg_wait: LinkedList::new(),
 			join: AvlTree::new(),
 			wait: AvlTree::new(),
 			conn_wait: AvlTree::new(),
 			futex: AvlTree::new(),
+			event_wait: AvlTree::new(),
 		}
 	}
 
@@ -315,16 +375,28 @@ impl ThreadList
 	{
 		match state {
 			ThreadState::Running => Some(&self.running[prid()]),
+			ThreadState::Handoff => Some(&self.handoff[prid()]),
 			ThreadState::Ready => Some(&self.ready),
 			ThreadState::Idle => Some(&self.idle[prid()]),
 			ThreadState::Destroy => Some(&self.destroy),
-			ThreadState::Sleep(_) => Some(&self.sleep),
+			// threads listening on an event with a timeout are woken by the timer like sleeping threads
+			ThreadState::Sleep(_) | ThreadState::EventSleep(..) => Some(&self.sleep),
+			ThreadState::Await(_) => Some(&self.awaiting),
+			ThreadState::IntWait => Some(&self.int_wait),
+			ThreadState::ExceptWait => Some(&self.except_wait),
+			ThreadState::Suspend => Some(&self.suspended),
+			ThreadState::RegWait => Some(&self.reg_wait),
 			ThreadState::Join(tuid) => Some(unsafe { unbound(&self.join.get(&tuid)?.list) }),
 			ThreadState::Waiting(tuid) => Some(unsafe { unbound(&self.wait.get(&tuid)?.list) }),
-			ThreadState::Listening(id) => Some(unsafe { unbound(&self.conn_wait.get(&id)?.list) }),
+			ThreadState::Listening(channel) => {
+				Some(unsafe { unbound(&self.conn_wait.get(&channel.as_ref().unwrap().id())?.list) })
+			},
 			ThreadState::FutexBlock(id) => {
 				Some(unsafe { unbound(&self.futex.get(&id.as_ref().unwrap().fuid())?.list) })
 			},
+			ThreadState::EventListen(event) => {
+				Some(unsafe { unbound(&self.event_wait.get(&event.as_ref().unwrap().id())?.list) })
+			},
 		}
 	}
 
@@ -332,28 +404,38 @@ impl ThreadList
 	{
 		match state {
 			ThreadState::Running => Some(&mut self.running[prid()]),
+			ThreadState::Handoff => Some(&mut self.handoff[prid()]),
 			ThreadState::Ready => Some(&mut self.ready),
 			ThreadState::Idle => Some(&mut self.idle[prid()]),
 			ThreadState::Destroy => Some(&mut self.destroy),
-			ThreadState::Sleep(_) => Some(&mut self.sleep),
+			ThreadState::Sleep(_) | ThreadState::EventSleep(..) => Some(&mut self.sleep),
+			ThreadState::Await(_) => Some(&mut self.awaiting),
+			ThreadState::IntWait => Some(&mut self.int_wait),
+			ThreadState::ExceptWait => Some(&mut self.except_wait),
+			ThreadState::Suspend => Some(&mut self.suspended),
+			ThreadState::RegWait => Some(&mut self.reg_wait),
 			ThreadState::Join(tuid) => {
 				Some(unsafe { unbound_mut(&mut self.join.get_mut(&tuid)?.list) })
 			},
 			ThreadState::Waiting(tuid) => {
 				Some(unsafe { unbound_mut(&mut self.wait.get_mut(&tuid)?.list) })
 			},
-			ThreadState::Listening(id) => {
-				Some(unsafe { unbound_mut(&mut self.conn_wait.get_mut(&id)?.list) })
-			},
+			ThreadState::Listening(channel) => Some(unsafe {
+				unbound_mut(&mut self.conn_wait.get_mut(&channel.as_ref().unwrap().id())?.list)
+			}),
 			ThreadState::FutexBlock(id) => Some(unsafe {
 				unbound_mut(&mut self.futex.get_mut(&id.as_ref().unwrap().fuid())?.list)
 			}),
+			ThreadState::EventListen(event) => Some(unsafe {
+				unbound_mut(&mut self.event_wait.get_mut(&event.as_ref().unwrap().id())?.list)
+			}),
 		}
 	}
 
 	fn ensure_running(&mut self, prid: usize) {
 		for _ in self.running.len()..=prid {
 			self.running.push(LinkedList::new());
+			self.handoff.push(LinkedList::new());
 			self.idle.push(LinkedList::new());
 		}
 	}
@@ -460,7 +542,8 @@ impl ThreadListGuard
 					}
 				}
 			},
-			ThreadState::Listening(cpid) => {
+			ThreadState::Listening(channel) => {
+				let cpid = unsafe { channel.as_ref().unwrap().id() };
 				if self.lock().conn_wait.get(&cpid).is_none() {
 					let node = TLTreeNode::new();
 					// NOTE: this is non allocing AvlTree, which returns the value it tried to insert if there was already a valus in the tree
@@ -483,6 +566,18 @@ impl ThreadListGuard
 					}
 				}
 			},
+			ThreadState::EventListen(event) => {
+				let id = unsafe { event.as_ref().unwrap().id() };
+				if self.lock().event_wait.get(&id).is_none() {
+					let node = TLTreeNode::new();
+					// NOTE: this is non allocing AvlTree, which returns the value it tried to insert if there was already a valus in the tree
+					if let Err(val) = self.lock().event_wait.insert(id, node) {
+						unsafe {
+							TLTreeNode::dealloc(val);
+						}
+					}
+				}
+			},
 			_ => (),
 		}
 	}
@@ -506,7 +601,8 @@ impl ThreadListGuard
 					}
 				}
 			},
-			ThreadState::Listening(cpid) => {
+			ThreadState::Listening(channel) => {
+				let cpid = unsafe { channel.as_ref().unwrap().id() };
 				if let Some(node) = self.lock().conn_wait.remove(&cpid) {
 					assert_eq!(node.list.len(), 0);
 					unsafe {
@@ -523,6 +619,15 @@ impl ThreadListGuard
 					}
 				}
 			},
+			ThreadState::EventListen(event) => {
+				let id = unsafe { event.as_ref().unwrap().id() };
+				if let Some(node) = self.lock().event_wait.remove(&id) {
+					assert_eq!(node.list.len(), 0);
+					unsafe {
+						TLTreeNode::dealloc(node);
+					}
+				}
+			},
 			_ => (),
 		}
 	}
@@ -673,6 +778,18 @@ pub fn proc_get(pid: Pid) -> Option<Arc<Process>>
 	proc_list.lock().get(&pid).cloned()
 }
 
+// the process list holds the kernel's strong reference to each process, which is dropped when the process terminates
+pub fn proc_insert(process: Arc<Process>)
+{
+	proc_list.lock().insert(process.pid(), process);
+}
+
+// returns every process that is currently in the process list
+pub fn proc_all() -> Vec<Arc<Process>>
+{
+	proc_list.lock().values().cloned().collect()
+}
+
 pub fn block(state: ThreadState)
 {
 	match state {
@@ -710,6 +827,7 @@ pub fn init() -> Result<(), Err>
 	}
 
 	kernel_proc.new_thread(thread_cleaner as usize, Some("thread_cleaner".to_string()))?;
+	kernel_proc.new_thread(except::except_dispatcher as usize, Some("except_dispatcher".to_string()))?;
 
 	// rip will be set on first context switch
 	let idle_thread = Thread::new_idle(
@@ -2,7 +2,7 @@ use core::cell::Cell;
 use core::ops::{Index, IndexMut};
 use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use core::ptr::NonNull;
-use alloc::alloc::{Allocator, Global, Layout};
+use alloc::alloc::{Global, Layout};
 use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
 
@@ -10,9 +10,12 @@ use spin::Mutex;
 use bitflags::bitflags;
 
 use crate::uses::*;
-use crate::cap::{CapMap, CapSys, CapObjectType, CapObject};
+use crate::cap::{CapFlags, CapId, CapMap, CapSys, CapObjectType, CapObject, Capability};
 use crate::key::Key;
 use crate::ipc::channel::Channel;
+use crate::ipc::registry;
+use crate::event::Event;
+use crate::int::interrupt::{IntAllocator, Interrupt};
 use crate::apic::lapic::Ipi;
 use crate::mem::{VirtRange, PAGE_SIZE};
 use crate::mem::phys_alloc::zm;
@@ -20,18 +23,23 @@ use crate::mem::virt_alloc::{
 	AllocType, FAllocerType, PageMappingFlags, VirtLayout, VirtLayoutElement, VirtMapper,
 };
 use crate::mem::shared_mem::SharedMem;
-use crate::upriv::PrivLevel;
+use crate::mem::allocator::Allocator;
+use crate::upriv::{PrivLevel, IOPRIV_UID};
 use crate::util::{CpuMarker, AvlTree, Futex, IMutex, LinkedList, MemOwner, UniqueMut, UniqueRef};
 use crate::syscall::udata::{UserArray, UserData, UserPageArray};
 use super::{
-	Tid, int_sched, proc_c, proc_list, thread_c, tlist, Registers, TLTreeNode, ThreadList,
+	Tid, int_sched, proc_c, proc_list, thread_c, tlist, Registers, Spawner, TLTreeNode, ThreadList,
 };
 use super::thread::{ConnSaveState, Stack, ThreadRef, Thread, ThreadState};
+use super::except::UserException;
 use super::elf::{ElfParser, Section};
 use super::sync::FutexMap;
 
 static NEXT_PID: AtomicUsize = AtomicUsize::new(0);
 
+// pages charged to the allocator of a process made by process_new, for the kernel data used by the process
+const PROCESS_PAGES: usize = 1;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 struct SpawnMemPtr
@@ -130,9 +138,19 @@ pub struct Process
 	threads: Mutex<BTreeMap<Tid, MemOwner<Thread>>>,
 
 	futex: FutexMap,
+	processes: CapMap<Process>,
 	smem: CapMap<SharedMem>,
 	channels: CapMap<Channel>,
 	keys: CapMap<Key>,
+	events: CapMap<Event>,
+	allocators: CapMap<Allocator>,
+	int_allocators: CapMap<IntAllocator>,
+	spawners: CapMap<Spawner>,
+	interrupts: CapMap<Interrupt>,
+	// event that is sent when a thread in this process causes an exception
+	except_event: Mutex<Option<Arc<Event>>>,
+	// allocator the process is charged to, processes made by the kernel are not charged to any allocator
+	allocator: Mutex<Option<Arc<Allocator>>>,
 
 	pub addr_space: VirtMapper<FAllocerType>,
 }
@@ -142,9 +160,9 @@ impl Process
 	// NOTE: must insert into process list before making a thread
 	pub fn new(uid: PrivLevel, name: String, launch_path: String) -> Arc<Self>
 	{
-		let pid = NEXT_PID.fetch_add(1, Ordering::Relaxed);
-		Arc::new_cyclic(|weak| Self {
-			pid: Pid::from(pid),
+		let pid = Pid::from(NEXT_PID.fetch_add(1, Ordering::Relaxed));
+		let process = Arc::new_cyclic(|weak| Self {
+			pid,
 			name,
 			launch_path,
 			self_ref: weak.clone(),
@@ -153,20 +171,83 @@ impl Process
 			uid,
 			next_tid: AtomicUsize::new(0),
 			threads: Mutex::new(BTreeMap::new()),
-			futex: FutexMap::new(),
-			smem: CapMap::new(),
-			channels: CapMap::new(),
-			keys: CapMap::new(),
+			futex: FutexMap::new(pid),
+			processes: CapMap::new(pid),
+			smem: CapMap::new(pid),
+			channels: CapMap::new(pid),
+			keys: CapMap::new(pid),
+			events: CapMap::new(pid),
+			allocators: CapMap::new(pid),
+			int_allocators: CapMap::new(pid),
+			spawners: CapMap::new(pid),
+			interrupts: CapMap::new(pid),
+			except_event: Mutex::new(None),
+			allocator: Mutex::new(None),
 			addr_space: VirtMapper::new(&zm),
-		})
+		});
+
+		// a process's capability to itself is weak so it does not keep itself alive
+		// it is always the first process capability, so it has the same id in every process
+		process.processes.insert(Capability::new_weak(
+			&process,
+			CapFlags::READ | CapFlags::PROD | CapFlags::WRITE,
+		));
+
+		process
+	}
+
+	// loads the early-init process from elf_data, and gives it the capabilities the kernel passes to early-init
+	pub fn early_init(elf_data: &[u8]) -> Result<Arc<Self>, Err>
+	{
+		Self::load_elf(
+			elf_data,
+			PrivLevel::new(IOPRIV_UID),
+			"early-init".to_string(),
+			"initrd;/early-init".to_string(),
+			true,
+		)
+	}
+
+	// gives the process the capabilities the kernel passes to early-init
+	// no other process gets these from the kernel, they have to be passed down by capability
+	// TODO: put these in the init capability array described in aurora_kernel_init_state
+	fn insert_init_caps(&self)
+	{
+		self.allocators.insert(Allocator::root_cap());
+		self.spawners.insert(Spawner::new());
+		self.int_allocators.insert(IntAllocator::new());
+	}
+
+	// charges the pages used by the process's kernel data to allocator, they are uncharged when the process is freed
+	// returns OutOfMem if allocator doesn't have enough pages left
+	pub fn charge_to(&self, allocator: Arc<Allocator>) -> Result<(), SysErr>
+	{
+		allocator.charge(PROCESS_PAGES)?;
+		*self.allocator.lock() = Some(allocator);
+		Ok(())
 	}
 
 	// NOTE: this doesn't quite adhere to elf format I think
 	// ignores align field, does not enforce that p_vaddr == P_offset % p_align
 	// different segments also must not have any overlapping page frames
 	pub fn from_elf(elf_data: &[u8], uid: PrivLevel, name: String, launch_path: String) -> Result<Arc<Self>, Err>
+	{
+		Self::load_elf(elf_data, uid, name, launch_path, false)
+	}
+
+	// if init_caps is set, the early-init capabilities are inserted before the process's first thread can run
+	fn load_elf(
+		elf_data: &[u8],
+		uid: PrivLevel,
+		name: String,
+		launch_path: String,
+		init_caps: bool,
+	) -> Result<Arc<Self>, Err>
 	{
 		let process = Process::new(uid, name, launch_path);
+		if init_caps {
+			process.insert_init_caps();
+		}
 
 		let elf = ElfParser::new(elf_data)?;
 		let sections = elf.program_headers();
@@ -375,6 +456,11 @@ impl Process
 		&self.futex
 	}
 
+	pub fn processes(&self) -> &CapMap<Process>
+	{
+		&self.processes
+	}
+
 	pub fn smem(&self) -> &CapMap<SharedMem>
 	{
 		&self.smem
@@ -390,16 +476,146 @@ impl Process
 		&self.keys
 	}
 
+	pub fn events(&self) -> &CapMap<Event>
+	{
+		&self.events
+	}
+
+	pub fn allocators(&self) -> &CapMap<Allocator>
+	{
+		&self.allocators
+	}
+
+	pub fn int_allocators(&self) -> &CapMap<IntAllocator>
+	{
+		&self.int_allocators
+	}
+
+	pub fn spawners(&self) -> &CapMap<Spawner>
+	{
+		&self.spawners
+	}
+
+	pub fn interrupts(&self) -> &CapMap<Interrupt>
+	{
+		&self.interrupts
+	}
+
+	// the previously bound event, if any, will no longer recieve exceptions
+	pub fn bind_except(&self, event: Arc<Event>) {
+		*self.except_event.lock() = Some(event);
+	}
+
+	pub fn except_event(&self) -> Option<Arc<Event>> {
+		self.except_event.lock().clone()
+	}
+
 	pub fn get_capmap(&self, typ: CapObjectType) -> &dyn CapSys {
 		match typ {
+			CapObjectType::Process => &self.processes,
 			CapObjectType::Channel => &self.channels,
 			CapObjectType::Futex => &self.futex,
 			CapObjectType::SMem => &self.smem,
 			CapObjectType::Key => &self.keys,
 			CapObjectType::Mmio => todo!(),
-			CapObjectType::Interrupt => todo!(),
+			CapObjectType::Interrupt => &self.interrupts,
 			CapObjectType::Port => todo!(),
+			CapObjectType::Event => &self.events,
+			CapObjectType::Allocator => &self.allocators,
+			CapObjectType::IntAllocator => &self.int_allocators,
+			CapObjectType::Spawner => &self.spawners,
+		}
+	}
+
+	// clones the capability into another process, and returns the id of the new capability
+	// returns None if the capability does not exist
+	pub fn clone_cap_to(&self, id: CapId, other: &Process) -> Option<CapId> {
+		Some(match id.cap_object_type()? {
+			CapObjectType::Process => other.processes.insert(self.processes.clone_from(id)?),
+			CapObjectType::Channel => other.channels.insert(self.channels.clone_from(id)?),
+			CapObjectType::Futex => other.futex.insert(self.futex.clone_from(id)?),
+			CapObjectType::SMem => other.smem.insert(self.smem.clone_from(id)?),
+			CapObjectType::Key => other.keys.insert(self.keys.clone_from(id)?),
+			CapObjectType::Event => other.events.insert(self.events.clone_from(id)?),
+			CapObjectType::Interrupt => other.interrupts.insert(self.interrupts.clone_from(id)?),
+			CapObjectType::Allocator => other.allocators.insert(self.allocators.clone_from(id)?),
+			CapObjectType::IntAllocator => other.int_allocators.insert(self.int_allocators.clone_from(id)?),
+			CapObjectType::Spawner => other.spawners.insert(self.spawners.clone_from(id)?),
+			_ => return None,
+		})
+	}
+
+	// clones the capability into another process with the privelidges in flags, and returns the id of the new capability
+	// the new capability is weak if flags has the weak flag set, and upgrading requires the upgrade privelidge
+	pub fn clone_cap_with(&self, id: CapId, other: &Process, flags: CapFlags) -> Result<CapId, SysErr> {
+		match id.cap_object_type().ok_or(SysErr::InvlId)? {
+			CapObjectType::Process => self.processes.clone_to(id, &other.processes, flags),
+			CapObjectType::Channel => self.channels.clone_to(id, &other.channels, flags),
+			CapObjectType::Futex => self.futex.clone_to(id, &other.futex, flags),
+			CapObjectType::SMem => self.smem.clone_to(id, &other.smem, flags),
+			CapObjectType::Key => self.keys.clone_to(id, &other.keys, flags),
+			CapObjectType::Event => self.events.clone_to(id, &other.events, flags),
+			CapObjectType::Interrupt => self.interrupts.clone_to(id, &other.interrupts, flags),
+			CapObjectType::Allocator => self.allocators.clone_to(id, &other.allocators, flags),
+			CapObjectType::IntAllocator => self.int_allocators.clone_to(id, &other.int_allocators, flags),
+			CapObjectType::Spawner => self.spawners.clone_to(id, &other.spawners, flags),
+			_ => Err(SysErr::InvlId),
+		}
+	}
+
+	// like clone_cap_with, but the original capability is destroyed
+	// memory capabilities are unmapped from this process before they are moved
+	pub fn move_cap_with(&self, id: CapId, other: &Process, flags: CapFlags) -> Result<CapId, SysErr> {
+		match id.cap_object_type().ok_or(SysErr::InvlId)? {
+			CapObjectType::Process => self.processes.move_to(id, &other.processes, flags),
+			CapObjectType::Channel => self.channels.move_to(id, &other.channels, flags),
+			CapObjectType::Futex => self.futex.move_to(id, &other.futex, flags),
+			CapObjectType::SMem => {
+				// the capability might not be mapped, in which case there is nothing to unmap
+				let _ = self.smem.unmap(self, id);
+				self.smem.move_to(id, &other.smem, flags)
+			},
+			CapObjectType::Key => self.keys.move_to(id, &other.keys, flags),
+			CapObjectType::Event => self.events.move_to(id, &other.events, flags),
+			CapObjectType::Interrupt => self.interrupts.move_to(id, &other.interrupts, flags),
+			CapObjectType::Allocator => self.allocators.move_to(id, &other.allocators, flags),
+			CapObjectType::IntAllocator => self.int_allocators.move_to(id, &other.int_allocators, flags),
+			CapObjectType::Spawner => self.spawners.move_to(id, &other.spawners, flags),
+			_ => Err(SysErr::InvlId),
+		}
+	}
+
+	// destroys a capability of type typ in this process that is being revoked
+	// memory capabilities are unmapped before they are destroyed
+	pub fn revoke_cap(&self, typ: CapObjectType, id: CapId) {
+		if typ == CapObjectType::SMem {
+			// the capability might not be mapped
+			let _ = self.smem.unmap(self, id);
 		}
+		self.get_capmap(typ).destroy(id);
+	}
+
+	// moves the capability into another process, and returns the id of the new capability
+	// memory capabilities are unmapped from this process before they are moved
+	// returns None if the capability does not exist
+	pub fn move_cap_to(&self, id: CapId, other: &Process) -> Option<CapId> {
+		Some(match id.cap_object_type()? {
+			CapObjectType::Process => other.processes.insert(self.processes.remove(id)?),
+			CapObjectType::Channel => other.channels.insert(self.channels.remove(id)?),
+			CapObjectType::Futex => other.futex.insert(self.futex.remove(id)?),
+			CapObjectType::SMem => {
+				// the capability might not be mapped, in which case there is nothing to unmap
+				let _ = self.smem.unmap(self, id);
+				other.smem.insert(self.smem.remove(id)?)
+			},
+			CapObjectType::Key => other.keys.insert(self.keys.remove(id)?),
+			CapObjectType::Event => other.events.insert(self.events.remove(id)?),
+			CapObjectType::Interrupt => other.interrupts.insert(self.interrupts.remove(id)?),
+			CapObjectType::Allocator => other.allocators.insert(self.allocators.remove(id)?),
+			CapObjectType::IntAllocator => other.int_allocators.insert(self.int_allocators.remove(id)?),
+			CapObjectType::Spawner => other.spawners.insert(self.spawners.remove(id)?),
+			_ => return None,
+		})
 	}
 
 	pub fn get_thread(&self, tid: Tid) -> Option<ThreadRef>
@@ -407,6 +623,16 @@ impl Process
 		unsafe { self.threads.lock().get(&tid).map(|memown| ThreadRef::from(memown.clone())) }
 	}
 
+	// takes the exceptions of all threads that have caused an exception that has not been sent yet
+	pub fn take_exceptions(&self) -> Vec<(Tid, UserException)>
+	{
+		self.threads
+			.lock()
+			.iter()
+			.filter_map(|(tid, thread)| Some((*tid, thread.take_exception()?)))
+			.collect()
+	}
+
 	// returns false if thread with tid is already inserted or tid was not gotten by next tid func
 	pub fn insert_thread(&self, thread: MemOwner<Thread>) -> bool
 	{
@@ -488,6 +714,8 @@ impl Process
 		self.alive.store(false, Ordering::Release);
 		drop(thread_list);
 
+		registry::prune();
+
 		let mut cpd = cpud();
 		let lapic = cpd.lapic();
 
@@ -511,6 +739,23 @@ impl Process
 	}
 }
 
+impl Drop for Process {
+	fn drop(&mut self) {
+		if let Some(allocator) = self.allocator.lock().as_ref() {
+			allocator.uncharge(PROCESS_PAGES);
+		}
+	}
+}
+
+impl CapObject for Process {
+	fn cap_object_type() -> CapObjectType {
+		CapObjectType::Process
+	}
+
+	fn inc_ref(&self) {}
+	fn dec_ref(&self) {}
+}
+
 pub(super) fn ipi_process_exit_handler(_: &mut Registers, _: u64) -> bool {
 	// can't use proc_c hear because process may have been dropped
 	match thread_c().process() {
@@ -0,0 +1,38 @@
+use alloc::sync::{Arc, Weak};
+
+use spin::Mutex;
+
+use crate::uses::*;
+use crate::cap::{CapFlags, CapObject, CapObjectType, Capability};
+use super::Process;
+
+// permission to create processes, and a record of the processes that were made with it
+#[derive(Debug)]
+pub struct Spawner {
+	processes: Mutex<Vec<Weak<Process>>>,
+}
+
+impl Spawner {
+	pub fn new() -> Capability<Self> {
+		let spawner = Spawner {
+			processes: Mutex::new(Vec::new()),
+		};
+		Capability::new(Arc::new(spawner), CapFlags::READ | CapFlags::PROD | CapFlags::WRITE)
+	}
+
+	// records that process was made with this spawner, and forgets any processes that have been freed
+	pub fn add(&self, process: &Arc<Process>) {
+		let mut processes = self.processes.lock();
+		processes.retain(|process| process.strong_count() != 0);
+		processes.push(Arc::downgrade(process));
+	}
+}
+
+impl CapObject for Spawner {
+	fn cap_object_type() -> CapObjectType {
+		CapObjectType::Spawner
+	}
+
+	fn inc_ref(&self) {}
+	fn dec_ref(&self) {}
+}
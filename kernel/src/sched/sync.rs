@@ -5,8 +5,8 @@ use alloc::sync::Arc;
 use spin::{Mutex, MutexGuard};
 
 use crate::uses::*;
-use crate::cap::{CapObject, CapObjectType, CapFlags, Capability, CapId, CapSys};
-use super::{block, tlist, ThreadState};
+use crate::cap::{CapObject, CapObjectType, CapFlags, Capability, CapId, CapNode, CapSys};
+use super::{block, tlist, Pid, ThreadState};
 
 crate::make_id_type!(Fuid);
 
@@ -67,7 +67,7 @@ impl KFutex
 	}
 
 	// returns true if successfully blocked
-	fn block(&self) -> bool
+	pub fn block(&self) -> bool
 	{
 		if self.wait_count.fetch_add(1, Ordering::AcqRel) >= 0 {
 			// in order to solve race condition with unblock and drop, we use this lock
@@ -87,7 +87,7 @@ impl KFutex
 		true
 	}
 
-	fn unblock(&self, n: usize) -> usize
+	pub fn unblock(&self, n: usize) -> usize
 	{
 		let state = ThreadState::FutexBlock(self as *const _);
 		let _lock = self.block_lock.lock();
@@ -134,6 +134,8 @@ impl CapObject for KFutex {
 #[derive(Debug)]
 pub struct FutexMap
 {
+	// process that owns the capabilities, see CapMap
+	pid: Pid,
 	// TODO: make this prettier
 	data: Mutex<BTreeMap<CapId, Capability<KFutex>>>,
 	next_id: AtomicUsize,
@@ -141,9 +143,10 @@ pub struct FutexMap
 
 impl FutexMap
 {
-	pub fn new() -> Self
+	pub fn new(pid: Pid) -> Self
 	{
 		FutexMap {
+			pid,
 			data: Mutex::new(BTreeMap::new()),
 			next_id: AtomicUsize::new(0),
 		}
@@ -152,12 +155,17 @@ impl FutexMap
 	pub fn insert(&self, mut cap: Capability<KFutex>) -> CapId {
 		let id = self.next_id.fetch_add(1, Ordering::Relaxed);
 		let id = cap.set_base_id(id);
-		self.data.lock().insert(id, cap);
+		// the node is updated while the map is locked, so revoke can't miss the capability
+		let mut data = self.data.lock();
+		cap.node().add_cap(self.pid, id);
+		data.insert(id, cap);
 		id
 	}
 
 	pub fn remove(&self, id: CapId) -> Option<Capability<KFutex>> {
-		self.data.lock().remove(&id)
+		let cap = self.data.lock().remove(&id)?;
+		cap.node().remove_cap(self.pid, id);
+		Some(cap)
 	}
 
 	pub fn clone_from(&self, id: CapId) -> Option<Capability<KFutex>> {
@@ -165,12 +173,29 @@ impl FutexMap
 		Some(lock.get(&id)?.clone())
 	}
 
+	pub fn clone_to(&self, id: CapId, other: &FutexMap, flags: CapFlags) -> Result<CapId, SysErr> {
+		let lock = self.data.lock();
+		let cap = lock.get(&id).ok_or(SysErr::InvlId)?.clone_with(flags)?;
+		drop(lock);
+		Ok(other.insert(cap))
+	}
+
+	pub fn move_to(&self, id: CapId, other: &FutexMap, flags: CapFlags) -> Result<CapId, SysErr> {
+		let mut lock = self.data.lock();
+		let cap = lock.get(&id).ok_or(SysErr::InvlId)?.clone_with(flags)?;
+		if let Some(old) = lock.remove(&id) {
+			old.node().remove_cap(self.pid, id);
+		}
+		drop(lock);
+		Ok(other.insert(cap))
+	}
+
 	pub fn block(&self, cid: CapId) -> Result<(), SysErr>
 	{
 		// I don't think this is a race condition
 		let lock = self.data.lock();
 		let cap = lock.get(&cid).ok_or(SysErr::InvlId)?;
-		let futex = cap.arc_clone();
+		let futex = cap.upgrade()?;
 		let flags = cap.flags();
 		drop(lock);
 
@@ -194,7 +219,7 @@ impl FutexMap
 		if !futex.flags().contains(CapFlags::READ) {
 			Err(SysErr::InvlPerm)
 		} else {
-			Ok(futex.object().unblock(n))
+			Ok(futex.upgrade()?.unblock(n))
 		}
 	}
 }
@@ -204,11 +229,42 @@ impl CapSys for FutexMap {
 		self.remove(id).is_some()
 	}
 
-	fn clone_cap(&self, id: CapId, flags: CapFlags) -> Option<CapId> {
+	fn clone_cap(&self, id: CapId, flags: CapFlags) -> Result<CapId, SysErr> {
 		let lock = self.data.lock();
-		let cap = lock.get(&id)?;
-		let new_cap = Capability::and_from_flags(cap, flags);
+		let cap = lock.get(&id).ok_or(SysErr::InvlId)?;
+		let new_cap = cap.clone_with(flags)?;
 		drop(lock);
-		Some(self.insert(new_cap))
+		Ok(self.insert(new_cap))
+	}
+
+	fn weak_is_alive(&self, id: CapId) -> SysErr {
+		match self.data.lock().get(&id) {
+			Some(cap) if !cap.is_weak() => SysErr::InvlArgs,
+			Some(cap) if cap.is_alive() => SysErr::Ok,
+			Some(_) => SysErr::InvlWeak,
+			None => SysErr::InvlId,
+		}
+	}
+
+	fn cap_info(&self, id: CapId) -> Result<(CapFlags, [usize; 2]), SysErr> {
+		let lock = self.data.lock();
+		let cap = lock.get(&id).ok_or(SysErr::InvlId)?;
+		Ok((cap.flags(), cap.info()))
+	}
+
+	fn cap_node(&self, id: CapId) -> Result<Arc<CapNode>, SysErr> {
+		let lock = self.data.lock();
+		Ok(lock.get(&id).ok_or(SysErr::InvlId)?.node().clone())
+	}
+}
+
+impl Drop for FutexMap
+{
+	// the capabilities are freed with the map, so they are no longer in their nodes
+	fn drop(&mut self)
+	{
+		for (id, cap) in self.data.lock().iter() {
+			cap.node().remove_cap(self.pid, *id);
+		}
 	}
 }
@@ -5,7 +5,7 @@ use crate::syscall::udata::{fetch_data, UserArray, UserData, UserString};
 use crate::syscall::SyscallVals;
 use crate::sysret;
 use crate::mem::PAGE_SIZE;
-use crate::cap::CapId;
+use crate::cap::{CapId, CapFlags, Capability};
 use super::*;
 
 // FIXME: make sure uid is valid once uid system is added to kernel
@@ -40,7 +40,65 @@ pub extern "C" fn spawn(vals: &mut SyscallVals)
 		Err(err) => sysret!(vals, err.num(), 0),
 	};
 
-	sysret!(vals, SysErr::Ok.num(), process.pid().into());
+	// the parent gets a weak capability, so it can inject capabilities into the child without keeping it alive
+	let cid = proc_c().processes().insert(Capability::new_weak(
+		&process,
+		CapFlags::READ | CapFlags::PROD | CapFlags::WRITE,
+	));
+
+	sysret!(vals, SysErr::Ok.num(), process.pid().into(), cid.into());
+}
+
+pub extern "C" fn process_new(vals: &mut SyscallVals) {
+	let current = proc_c();
+
+	let allocator = match current.allocators().fetch(CapId::from(vals.a1), CapFlags::PROD) {
+		Ok(allocator) => allocator,
+		Err(err) => sysret!(vals, err.num(), 0),
+	};
+
+	let spawner = match current.spawners().fetch(CapId::from(vals.a2), CapFlags::PROD) {
+		Ok(spawner) => spawner,
+		Err(err) => sysret!(vals, err.num(), 0),
+	};
+
+	// the capability can never be upgraded, so processes can't keep each other alive in a reference cycle
+	let flags = CapFlags::from_bits_truncate(vals.options as usize)
+		& (CapFlags::READ | CapFlags::PROD | CapFlags::WRITE);
+
+	// the process has no threads or memory yet, the creator sets it up with its capability
+	// it is named after its creator, since process_new doesn't take a name
+	let process = Process::new(
+		current.uid(),
+		format!("{}/child", current.name()),
+		current.launch_path().clone(),
+	);
+	if let Err(err) = process.charge_to(allocator) {
+		sysret!(vals, err.num(), 0);
+	}
+	spawner.add(&process);
+
+	let cid = current.processes().insert(Capability::new_weak(&process, flags));
+	proc_insert(process);
+
+	sysret!(vals, SysErr::Ok.num(), cid.into());
+}
+
+pub extern "C" fn process_exit(vals: &mut SyscallVals) {
+	let process = match proc_c().processes().fetch(CapId::from(vals.a1), CapFlags::WRITE) {
+		Ok(process) => process,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	if process.pid() == proc_c().pid() {
+		// a thread can't terminate its own process, so the thread cleaner terminates it
+		term_list.lock().push(process);
+		block(ThreadState::Destroy);
+		panic!("thread resumed after its process exited");
+	}
+
+	process.terminate();
+	sysret!(vals, SysErr::Ok.num());
 }
 
 pub extern "C" fn thread_new(vals: &mut SyscallVals)
@@ -67,12 +125,28 @@ pub extern "C" fn thread_block(vals: &mut SyscallVals)
 		1 => block(ThreadState::Destroy),
 		2 => block(ThreadState::Sleep(arg as u64)),
 		3 => block(ThreadState::Join(Tuid::new(proc_c().pid(), Tid::from(arg)))),
+		4 => thread_c().await_async_event(),
 		_ => sysret!(vals, SysErr::InvlArgs.num()),
 	}
 
 	sysret!(vals, SysErr::Ok.num());
 }
 
+pub extern "C" fn process_bind_except(vals: &mut SyscallVals) {
+	let process = match proc_c().processes().fetch(CapId::from(vals.a1), CapFlags::WRITE) {
+		Ok(process) => process,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	let event = match proc_c().events().fetch(CapId::from(vals.a2), CapFlags::WRITE) {
+		Ok(event) => event,
+		Err(err) => sysret!(vals, err.num()),
+	};
+
+	process.bind_except(event);
+	sysret!(vals, SysErr::Ok.num());
+}
+
 pub extern "C" fn futex_new(vals: &mut SyscallVals) {
 	let futex = KFutex::new();
 	let id = proc_c().futex().insert(futex);
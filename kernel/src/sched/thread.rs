@@ -3,7 +3,7 @@ use core::fmt;
 use core::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
 use core::ops::Deref;
 use core::mem::transmute;
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::alloc::{Allocator, Global, Layout};
 use alloc::sync::{Arc, Weak};
 
@@ -12,7 +12,11 @@ use ptr::NonNull;
 use sys_consts::SysErr;
 
 use crate::uses::*;
-use crate::ipc::Ipcid;
+use crate::ipc::channel::Channel;
+use crate::ipc::registry;
+use crate::ipc::msg::RegMsg;
+use crate::event::{AsyncEvent, Event, EventArgs};
+use crate::int::interrupt;
 use crate::mem::phys_alloc::{zm, Allocation};
 use crate::mem::virt_alloc::{
 	AllocType, FAllocerType, PageMappingFlags, VirtLayout, VirtLayoutElement, VirtMapper,
@@ -24,8 +28,11 @@ use crate::util::{
 	Futex, FutexGuard, IMutex, IMutexGuard, ListNode, MemOwner, UniqueMut, UniquePtr, UniqueRef,
 };
 use crate::time::timer;
+use crate::syscall::SyscallVals;
+use crate::cap::CapId;
 use super::process::Process;
-use super::{int_sched, thread_c, tlist, KFutex, Registers, ThreadList, Pid};
+use super::except::{self, UserException};
+use super::{block, int_sched, thread_c, tlist, KFutex, Registers, ThreadList, Pid};
 
 // TODO: implement support for growing stack
 #[derive(Debug)]
@@ -111,6 +118,8 @@ impl Stack
 pub enum ThreadState
 {
 	Running,
+	// thread will be switched to directly the next time the scheduler runs on this cpu, without going through the ready queue
+	Handoff,
 	Ready,
 	// idle thread
 	Idle,
@@ -123,8 +132,22 @@ pub enum ThreadState
 	Join(Tuid),
 	// virtual address currently waiting on
 	FutexBlock(*const KFutex),
-	// connection cpid we are waiting for a reply from
-	Listening(Ipcid),
+	// channel we are waiting to send or recieve a message on
+	Listening(*const Channel),
+	// event we are synchronously listening on
+	EventListen(*const Event),
+	// event we are synchronously listening on, and nsecs to stop listening at
+	EventSleep(*const Event, u64),
+	// waiting for an asynchronous event to be recieved, the pointer is the waiting thread
+	Await(*const Thread),
+	// waiting for a hardware interrupt to fire, only used by the interrupt dispatcher thread
+	IntWait,
+	// waiting for a thread to cause an exception in userspace, only used by the exception dispatcher thread
+	ExceptWait,
+	// suspended after causing an exception in userspace, until the process exits
+	Suspend,
+	// waiting for the default registration of a registry name to be removed
+	RegWait,
 }
 
 impl ThreadState
@@ -134,6 +157,14 @@ impl ThreadState
 	{
 		match self {
 			Self::FutexBlock(id) => unsafe { id.as_ref().unwrap().force_unlock() },
+			Self::Listening(channel) => unsafe { channel.as_ref().unwrap().force_unlock() },
+			Self::EventListen(event) | Self::EventSleep(event, _) => unsafe {
+				event.as_ref().unwrap().force_unlock()
+			},
+			Self::Await(thread) => unsafe { thread.as_ref().unwrap().async_events.force_unlock() },
+			Self::RegWait => unsafe { registry::force_unlock() },
+			Self::ExceptWait => unsafe { except::force_unlock() },
+			Self::IntWait => unsafe { interrupt::force_unlock() },
 			_ => (),
 		}
 	}
@@ -277,6 +308,22 @@ pub struct Thread
 
 	conn_data: Futex<Vec<ConnSaveState>>,
 	msg_recieve_regs: IMutex<Result<Registers, SysErr>>,
+	ipc_result: IMutex<SysErr>,
+	// message recieved in registers by the last channel operation
+	ipc_regs: IMutex<RegMsg>,
+	// badge of the message recieved by the last channel operation
+	ipc_badge: AtomicUsize,
+	// asynchronous events that have been recieved, but whose handlers have not been called yet
+	// this lock is held while a thread awaits events, and is released by the scheduler in ThreadState::atomic_process
+	async_events: Mutex<VecDeque<AsyncEvent>>,
+	// registers to return to once the currently running asynchronous event handler calls eret
+	async_save_regs: IMutex<Option<SyscallVals>>,
+	// arguments of the event recieved by the last synchronous event listen
+	event_args: IMutex<EventArgs>,
+	// weak capabilities referencing dead objects used by the current syscall, destroyed if it used weak_auto_destroy
+	dead_weaks: Futex<Vec<CapId>>,
+	// exception caused by this thread in userspace, that has not been sent to its process yet
+	exception: IMutex<Option<UserException>>,
 
 	msg_bufs: Futex<BTreeMap<VirtAddr, Allocation>>,
 
@@ -350,6 +397,14 @@ impl Thread
 			kstack,
 			conn_data: Futex::new(Vec::new()),
 			msg_recieve_regs: IMutex::new(Err(SysErr::Unknown)),
+			ipc_result: IMutex::new(SysErr::Unknown),
+			ipc_regs: IMutex::new(RegMsg::default()),
+			ipc_badge: AtomicUsize::new(0),
+			async_events: Mutex::new(VecDeque::new()),
+			async_save_regs: IMutex::new(None),
+			event_args: IMutex::new(EventArgs::default()),
+			dead_weaks: Futex::new(Vec::new()),
+			exception: IMutex::new(None),
 			msg_bufs: Futex::new(BTreeMap::new()),
 			prev: AtomicPtr::new(null_mut()),
 			next: AtomicPtr::new(null_mut()),
@@ -389,6 +444,14 @@ impl Thread
 			kstack: None,
 			conn_data: Futex::new(Vec::new()),
 			msg_recieve_regs: IMutex::new(Err(SysErr::Unknown)),
+			ipc_result: IMutex::new(SysErr::Unknown),
+			ipc_regs: IMutex::new(RegMsg::default()),
+			ipc_badge: AtomicUsize::new(0),
+			async_events: Mutex::new(VecDeque::new()),
+			async_save_regs: IMutex::new(None),
+			event_args: IMutex::new(EventArgs::default()),
+			dead_weaks: Futex::new(Vec::new()),
+			exception: IMutex::new(None),
 			msg_bufs: Futex::new(BTreeMap::new()),
 			prev: AtomicPtr::new(null_mut()),
 			next: AtomicPtr::new(null_mut()),
@@ -456,6 +519,147 @@ impl Thread
 		*self.state.lock() = state;
 	}
 
+	// sets the value returned by the channel operation this thread is blocked on, and makes the thread ready to run
+	pub fn ipc_wake(&self, result: SysErr)
+	{
+		self.ipc_wake_to(result, ThreadState::Ready);
+	}
+
+	// like ipc_wake, but the scheduler will switch directly to this thread the next time it runs on this cpu
+	pub fn ipc_handoff(&self, result: SysErr)
+	{
+		self.ipc_wake_to(result, ThreadState::Handoff);
+	}
+
+	fn ipc_wake_to(&self, result: SysErr, state: ThreadState)
+	{
+		*self.ipc_result.lock() = result;
+
+		let mut thread_list = tlist.lock();
+		if let ThreadState::Listening(_) = self.state() {
+			Thread::move_to(UniqueRef::new(self), state, &mut thread_list);
+		}
+	}
+
+	// moves a thread blocked on one channel to be blocked on another channel without waking it
+	pub fn ipc_move(&self, channel: &Channel)
+	{
+		let mut thread_list = tlist.lock();
+		if let ThreadState::Listening(_) = self.state() {
+			Thread::move_to(UniqueRef::new(self), ThreadState::Listening(channel as *const _), &mut thread_list);
+		}
+	}
+
+	pub fn ipc_result(&self) -> SysErr
+	{
+		*self.ipc_result.lock()
+	}
+
+	pub fn set_ipc_regs(&self, msg: RegMsg)
+	{
+		*self.ipc_regs.lock() = msg;
+	}
+
+	pub fn ipc_regs(&self) -> RegMsg
+	{
+		*self.ipc_regs.lock()
+	}
+
+	pub fn set_ipc_badge(&self, badge: usize)
+	{
+		self.ipc_badge.store(badge, Ordering::Release);
+	}
+
+	pub fn ipc_badge(&self) -> usize
+	{
+		self.ipc_badge.load(Ordering::Acquire)
+	}
+
+	pub fn set_exception(&self, exception: UserException)
+	{
+		*self.exception.lock() = Some(exception);
+	}
+
+	pub fn take_exception(&self) -> Option<UserException>
+	{
+		self.exception.lock().take()
+	}
+
+	// queues an asynchronous event, and wakes the thread if it is awaiting events
+	pub fn push_async_event(&self, event: AsyncEvent)
+	{
+		let mut async_events = self.async_events.lock();
+		async_events.push_back(event);
+
+		let mut thread_list = tlist.lock();
+		if let ThreadState::Await(_) = self.state() {
+			Thread::move_to(UniqueRef::new(self), ThreadState::Ready, &mut thread_list);
+		}
+	}
+
+	pub fn pop_async_event(&self) -> Option<AsyncEvent>
+	{
+		self.async_events.lock().pop_front()
+	}
+
+	// blocks until an asynchronous event has been recieved, returns immediately if one is already pending
+	// must only be called on the currently running thread
+	pub fn await_async_event(&self)
+	{
+		let async_events = self.async_events.lock();
+		if async_events.is_empty() {
+			// the scheduler unlocks async_events once this thread is blocked, so no events can be missed
+			block(ThreadState::Await(self as *const _));
+			core::mem::forget(async_events);
+		}
+	}
+
+	// if the thread is not already running an asynchronous event handler, pops the next recieved asynchronous event,
+	// and saves regs so they can be restored by eret once the event's handler returns
+	pub fn start_async_handler(&self, regs: &SyscallVals) -> Option<AsyncEvent>
+	{
+		let mut save_regs = self.async_save_regs.lock();
+		if save_regs.is_some() {
+			return None;
+		}
+
+		let event = self.pop_async_event()?;
+		*save_regs = Some(*regs);
+		Some(event)
+	}
+
+	// returns the registers saved when the running asynchronous event handler was called, or None if no handler is running
+	pub fn end_async_handler(&self) -> Option<SyscallVals>
+	{
+		self.async_save_regs.lock().take()
+	}
+
+	// sets the arguments of the event this thread is listening on, and makes the thread ready to run
+	pub fn event_wake(&self, args: EventArgs)
+	{
+		*self.event_args.lock() = args;
+
+		let mut thread_list = tlist.lock();
+		if let ThreadState::EventListen(_) | ThreadState::EventSleep(..) = self.state() {
+			Thread::move_to(UniqueRef::new(self), ThreadState::Ready, &mut thread_list);
+		}
+	}
+
+	pub fn event_args(&self) -> EventArgs
+	{
+		*self.event_args.lock()
+	}
+
+	pub fn push_dead_weak(&self, id: CapId)
+	{
+		self.dead_weaks.lock().push(id);
+	}
+
+	pub fn take_dead_weaks(&self) -> Vec<CapId>
+	{
+		core::mem::take(&mut *self.dead_weaks.lock())
+	}
+
 	/*pub fn rcv_regs(&self) -> &IMutex<Result<Registers, SysErr>>
 	{
 		&self.msg_recieve_regs
@@ -1,4 +1,5 @@
 pub use sys_consts::SysErr;
+use sys_consts::options::CommonOptions;
 
 use crate::uses::*;
 use crate::arch::x64::{
@@ -6,10 +7,24 @@ use crate::arch::x64::{
 };
 use crate::sched::sys::{
 	futex_new, futex_block, futex_unblock,
-	spawn, thread_block, thread_new,
+	spawn, thread_block, thread_new, process_new, process_exit, process_bind_except,
 };
-use crate::mem::sys::{mprotect, realloc, smem_new};
-use crate::cap::sys::{cap_destroy, cap_clone, cap_map, cap_unmap, cap_info};
+use crate::mem::sys::{mem_new, mem_reserve, mem_size, mem_unreserve, mprotect, realloc};
+use crate::cap::sys::{
+	cap_destroy, cap_clone, cap_move, cap_revoke, cap_map, cap_unmap, cap_info, weak_is_alive,
+	cap_destroy_many, cap_clone_many, cap_move_many,
+};
+use crate::ipc::sys::{
+	channel_new, channel_msg_props, channel_send, channel_recv, channel_nbsend, channel_nbrecv,
+	channel_asend, channel_arecv, channel_reply_recv, channel_call, channel_acall, reg, connect,
+};
+use crate::event::sys::{
+	event_new, event_argc, event_send, event_listen, event_nblisten, event_alisten, event_aabort, eret,
+	async_handler_ret,
+};
+use crate::int::sys::{int_new, int_vector, int_bind, int_eoi};
+use crate::sched::{proc_c, thread_c};
+use crate::cap::CapObjectType;
 use crate::util::io::sys_print_debug;
 
 pub mod udata;
@@ -20,23 +35,21 @@ extern "C" {
 
 pub type SyscallFunc = extern "C" fn(&mut SyscallVals) -> ();
 
+// userspace finds the slots of some syscalls in sys_consts::kernel_nums, so they must be updated if this is reordered
 #[no_mangle]
-static syscalls: [SyscallFunc; 42] = [
+static syscalls: [SyscallFunc; 61] = [
 	sys_print_debug,
 	// TODO: spawn
 	spawn,
-	// TODO: exit
-	sys_nop,
+	process_exit,
 	// TODO: kill
 	sys_nop,
 	// TODO: set_priv
 	sys_nop,
 	thread_new,
 	thread_block,
-	// TODO: reg
-	sys_nop,
-	// TODO: hret
-	sys_nop,
+	reg,
+	eret,
 	realloc,
 	// TODO: mprotect
 	mprotect,
@@ -44,7 +57,6 @@ static syscalls: [SyscallFunc; 42] = [
 	cap_clone,
 	cap_map,
 	cap_unmap,
-	// TODO: cap_info
 	cap_info,
 	// TODO: port_new
 	sys_nop,
@@ -54,13 +66,10 @@ static syscalls: [SyscallFunc; 42] = [
 	sys_nop,
 	// TODO: mmio_new
 	sys_nop,
-	// TODO: int_new
-	sys_nop,
-	// TODO: int_wait
-	sys_nop,
-	// TODO: int_eoi
-	sys_nop,
-	smem_new,
+	int_new,
+	int_bind,
+	int_eoi,
+	mem_new,
 	futex_new,
 	futex_block,
 	futex_unblock,
@@ -68,32 +77,39 @@ static syscalls: [SyscallFunc; 42] = [
 	sys_nop,
 	// TODO: key_id
 	sys_nop,
-	// TODO: channel_new
-	sys_nop,
-	// TODO: msg_buf_new
-	sys_nop,
+	channel_new,
+	channel_msg_props,
 	// TODO: msg_buf_destroy
 	sys_nop,
-	// TODO: send
-	sys_nop,
-	// TODO: recv
-	sys_nop,
-	// TODO: nbsend
-	sys_nop,
-	// TODO: nbrecv
-	sys_nop,
-	// TODO: asend
-	sys_nop,
-	// TODO: arecv
-	sys_nop,
-	// TODO: reply_recv
-	sys_nop,
-	// TODO: call
-	sys_nop,
-	// TODO: acall
-	sys_nop,
-	// TODO: aabort
-	sys_nop,
+	channel_send,
+	channel_recv,
+	channel_nbsend,
+	channel_nbrecv,
+	channel_asend,
+	channel_arecv,
+	channel_reply_recv,
+	channel_call,
+	channel_acall,
+	event_aabort,
+	connect,
+	event_new,
+	event_argc,
+	event_send,
+	event_listen,
+	event_nblisten,
+	event_alisten,
+	int_vector,
+	process_bind_except,
+	weak_is_alive,
+	cap_move,
+	cap_revoke,
+	cap_destroy_many,
+	cap_clone_many,
+	cap_move_many,
+	process_new,
+	mem_size,
+	mem_reserve,
+	mem_unreserve,
 ];
 
 // TODO: figure out if packed is needed
@@ -249,6 +265,29 @@ macro_rules! sysret {
 
 extern "C" fn sys_nop(_: &mut SyscallVals) {}
 
+// called by syscall_entry after every valid syscall, before returning to userspace
+#[no_mangle]
+extern "C" fn syscall_return(vals: &mut SyscallVals) {
+	let dead_weaks = thread_c().take_dead_weaks();
+	if CommonOptions::from_bits_truncate(vals.options).contains(CommonOptions::WEAK_AUTO_DESTROY) {
+		let process = proc_c();
+		for id in dead_weaks {
+			// mmio and port capabilities are never weak, so skip them like invalid ids
+			let capmap = match id.cap_object_type() {
+				Some(CapObjectType::Mmio | CapObjectType::Port) | None => continue,
+				Some(typ) => process.get_capmap(typ),
+			};
+
+			// the capability might have been in another process, so make sure it is actually a dead weak capability here
+			if capmap.weak_is_alive(id) == SysErr::InvlWeak {
+				capmap.destroy(id);
+			}
+		}
+	}
+
+	async_handler_ret(vals);
+}
+
 pub fn init()
 {
 	let efer = rdmsr(EFER_MSR);
@@ -1,5 +1,6 @@
 use crate::uses::*;
 use crate::mem::{VirtRange, PAGE_SIZE};
+use crate::mem::virt_alloc::PageMappingFlags;
 use crate::sched::proc_c;
 use crate::consts::KERNEL_VMA;
 
@@ -97,25 +98,59 @@ impl<T: UserData + Default> UserArray<T>
 		self.len
 	}
 
-	pub fn try_fetch(&self) -> Option<Vec<T>>
+	// returns the user memory holding the first len elements of the array, or None if it is not valid user memory
+	fn range(&self, len: usize) -> Option<VirtRange>
 	{
-		if !aligned_nonnull(self.ptr) {
+		if !aligned_nonnull(self.ptr) || len > self.len {
 			return None;
 		}
 
 		let range = VirtRange::new_unaligned(
 			VirtAddr::try_new(self.ptr as u64).ok()?,
-			self.len * size_of::<T>(),
+			len.checked_mul(size_of::<T>())?,
 		);
-		if !range.verify_umem() {
-			return None;
+		if range.verify_umem() {
+			Some(range)
+		} else {
+			None
 		}
+	}
+
+	pub fn try_fetch(&self) -> Option<Vec<T>>
+	{
+		let range = self.range(self.len)?;
 
 		proc_c().addr_space.range_map(range, |data| {
 			let slice = unsafe { core::slice::from_raw_parts(data.as_ptr() as *const T, self.len) };
 			Some(copy_to_heap(slice))
 		})
 	}
+
+	// returns true if the whole array is mapped and writable by userspace
+	pub fn writable(&self) -> bool
+	{
+		let range = match self.range(self.len) {
+			Some(range) => range,
+			None => return false,
+		};
+
+		proc_c().addr_space.range_map_flags(range, PageMappingFlags::USER | PageMappingFlags::WRITE, |_| Some(())).is_some()
+	}
+
+	// writes data to the start of the array
+	// returns None if data is longer than the array or the array is not mapped writable by userspace
+	pub fn try_write(&self, data: &[T]) -> Option<()>
+	{
+		let range = self.range(data.len())?;
+
+		proc_c().addr_space.range_map_flags(range, PageMappingFlags::USER | PageMappingFlags::WRITE, |mem| {
+			let ptr = mem.as_ptr() as *mut T;
+			for (i, elem) in data.iter().enumerate() {
+				unsafe { ptr::write_unaligned(ptr.add(i), *elem) }
+			}
+			Some(())
+		})
+	}
 }
 
 impl<T: UserData> Default for UserArray<T>
@@ -187,7 +222,16 @@ pub fn verify_uaddr(addr: usize) -> bool
 	addr < *KERNEL_VMA
 }
 
+// returns true if addr is a canonical user address, so it is safe to sysret with it in rip or rsp
+pub fn verify_ucanonical(addr: usize) -> bool
+{
+	VirtAddr::try_new(addr as u64).is_ok() && verify_uaddr(addr)
+}
+
 pub fn verify_umem(addr: usize, size: usize) -> bool
 {
-	verify_uaddr(addr + size - 1)
+	match addr.checked_add(size) {
+		Some(end) => end <= *KERNEL_VMA,
+		None => false,
+	}
 }
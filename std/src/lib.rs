@@ -7,7 +7,10 @@ pub mod io;
 pub mod os;
 
 mod macros;
+// the test harness links the host std, which provides the lang items
+#[cfg(not(test))]
 mod panicking;
+#[cfg(not(test))]
 mod rt;
 mod uses;
 
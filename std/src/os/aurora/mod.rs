@@ -1 +1,3 @@
 pub use sys;
+
+pub mod rpc;
@@ -0,0 +1,657 @@
+//! Typed remote procedure calls over channels
+//!
+//! The [`rpc`](crate::rpc) macro defines a protocol, and generates a request enum, a response enum,
+//! a client with 1 method per call, and a server trait with a dispatch loop
+//!
+//! ```ignore
+//! rpc! {
+//! 	pub protocol {
+//! 		request FsRequest;
+//! 		response FsResponse;
+//! 		client FsClient;
+//! 		server FsServer;
+//!
+//! 		Open => fn open(dir: Cap, flags: usize) -> (file: Cap);
+//! 		Read => fn read(file: Cap, offset: usize, len: usize) -> (mem: Cap, read: usize);
+//! 	}
+//! }
+//! ```
+use sys::{channel_call, channel_recv, channel_reply_recv, SysErr};
+
+use crate::uses::*;
+
+// the message layout is described in aurora_kernel_syscalls
+const HEADER_LEN: usize = 3;
+
+const LEN_WORD: usize = 0;
+const FLAGS_WORD: usize = 1;
+const REPLY_WORD: usize = 2;
+
+const MSG_MAP: usize = 1 << 1;
+const CAP_COUNT_SHIFT: usize = 32;
+
+/// A capability sent in a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cap(pub usize);
+
+/// A message buffer that is mapped into this process
+#[derive(Debug)]
+pub struct MsgBuf
+{
+	cid: usize,
+	ptr: *mut usize,
+	len: usize,
+}
+
+impl MsgBuf
+{
+	/// # Safety
+	///
+	/// the mem capability `cid` must be mapped at `ptr`, and be at least `len` words long
+	pub unsafe fn from_raw(cid: usize, ptr: *mut usize, len: usize) -> Self
+	{
+		MsgBuf {
+			cid,
+			ptr,
+			len,
+		}
+	}
+
+	pub fn cid(&self) -> usize
+	{
+		self.cid
+	}
+
+	/// size of the buffer in words
+	pub fn len(&self) -> usize
+	{
+		self.len
+	}
+
+	fn get(&self, index: usize) -> Option<usize>
+	{
+		if index < self.len {
+			unsafe { Some(ptr::read_volatile(self.ptr.add(index))) }
+		} else {
+			None
+		}
+	}
+
+	fn set(&self, index: usize, val: usize) -> bool
+	{
+		if index < self.len {
+			unsafe { ptr::write_volatile(self.ptr.add(index), val) }
+			true
+		} else {
+			false
+		}
+	}
+
+	// cid of the reply channel of the last recieved message, or 0 if there is none
+	fn reply(&self) -> usize
+	{
+		self.get(REPLY_WORD).unwrap_or(0)
+	}
+
+	// writes a message with no capabilities or data, which is not a valid request or response
+	fn set_empty(&self)
+	{
+		self.set(LEN_WORD, HEADER_LEN);
+		self.set(FLAGS_WORD, 0);
+		self.set(REPLY_WORD, 0);
+	}
+}
+
+/// Writes the capabilities and data words of a message
+///
+/// capabilities come before the data in a message,
+/// so the number of capabilities must be known before anything is written
+pub struct MsgWriter<'a>
+{
+	buf: &'a MsgBuf,
+	cap: usize,
+	cap_end: usize,
+	data: usize,
+	overflow: bool,
+}
+
+impl<'a> MsgWriter<'a>
+{
+	pub fn new(buf: &'a MsgBuf, cap_count: usize) -> Self
+	{
+		MsgWriter {
+			buf,
+			cap: HEADER_LEN,
+			cap_end: HEADER_LEN + cap_count,
+			data: HEADER_LEN + cap_count,
+			overflow: false,
+		}
+	}
+
+	pub fn write_word(&mut self, val: usize)
+	{
+		if self.buf.set(self.data, val) {
+			self.data += 1;
+		} else {
+			self.overflow = true;
+		}
+	}
+
+	pub fn write_cap(&mut self, cap: Cap)
+	{
+		if self.cap < self.cap_end && self.buf.set(self.cap, cap.0) {
+			self.cap += 1;
+		} else {
+			self.overflow = true;
+		}
+	}
+
+	pub fn write<T: RpcArg>(&mut self, arg: &T)
+	{
+		arg.write(self);
+	}
+
+	/// writes the message header
+	///
+	/// returns InvlArgs if the message did not fit in the buffer,
+	/// or if a different number of capabilities were written than were passed to [`new`](Self::new)
+	pub fn finish(self) -> Result<(), SysErr>
+	{
+		if self.overflow || self.cap != self.cap_end {
+			return Err(SysErr::InvlArgs);
+		}
+
+		let cap_count = self.cap_end - HEADER_LEN;
+		self.buf.set(LEN_WORD, self.data);
+		self.buf.set(FLAGS_WORD, cap_count << CAP_COUNT_SHIFT);
+		self.buf.set(REPLY_WORD, 0);
+		Ok(())
+	}
+}
+
+/// Reads the capabilities and data words of a recieved message
+pub struct MsgReader<'a>
+{
+	buf: &'a MsgBuf,
+	len: usize,
+	cap: usize,
+	cap_end: usize,
+	data: usize,
+}
+
+impl<'a> MsgReader<'a>
+{
+	pub fn new(buf: &'a MsgBuf) -> Option<Self>
+	{
+		let len = buf.get(LEN_WORD)?.min(buf.len());
+		let flags = buf.get(FLAGS_WORD)?;
+		let cap_count = flags >> CAP_COUNT_SHIFT;
+
+		// mapped messages have an address after each capability
+		let data = if flags & MSG_MAP != 0 {
+			HEADER_LEN + 2 * cap_count
+		} else {
+			HEADER_LEN + cap_count
+		};
+
+		Some(MsgReader {
+			buf,
+			len,
+			cap: HEADER_LEN,
+			cap_end: HEADER_LEN + cap_count,
+			data,
+		})
+	}
+
+	pub fn read_word(&mut self) -> Option<usize>
+	{
+		if self.data < self.len {
+			self.data += 1;
+			self.buf.get(self.data - 1)
+		} else {
+			None
+		}
+	}
+
+	pub fn read_cap(&mut self) -> Option<Cap>
+	{
+		if self.cap < self.cap_end && self.cap < self.len {
+			self.cap += 1;
+			self.buf.get(self.cap - 1).map(Cap)
+		} else {
+			None
+		}
+	}
+
+	pub fn read<T: RpcArg>(&mut self) -> Option<T>
+	{
+		T::read(self)
+	}
+}
+
+/// A value that can be sent as an argument or return value of a call
+pub trait RpcArg: Sized
+{
+	/// number of capabilities written by [`write`](Self::write)
+	const CAPS: usize = 0;
+
+	fn write(&self, msg: &mut MsgWriter);
+	fn read(msg: &mut MsgReader) -> Option<Self>;
+}
+
+impl RpcArg for Cap
+{
+	const CAPS: usize = 1;
+
+	fn write(&self, msg: &mut MsgWriter)
+	{
+		msg.write_cap(*self);
+	}
+
+	fn read(msg: &mut MsgReader) -> Option<Self>
+	{
+		msg.read_cap()
+	}
+}
+
+impl RpcArg for bool
+{
+	fn write(&self, msg: &mut MsgWriter)
+	{
+		msg.write_word(*self as usize);
+	}
+
+	fn read(msg: &mut MsgReader) -> Option<Self>
+	{
+		Some(msg.read_word()? != 0)
+	}
+}
+
+macro_rules! impl_rpc_arg_int {
+	($($ty:ty),*) => {
+		$(
+			impl RpcArg for $ty
+			{
+				fn write(&self, msg: &mut MsgWriter)
+				{
+					msg.write_word(*self as usize);
+				}
+
+				fn read(msg: &mut MsgReader) -> Option<Self>
+				{
+					Some(msg.read_word()? as $ty)
+				}
+			}
+		)*
+	};
+}
+
+impl_rpc_arg_int!(usize, isize, u64, i64, u32, i32, u16, i16, u8, i8);
+
+/// A request or response of a protocol, implemented by the [`rpc`](crate::rpc) macro
+pub trait RpcMsg: Sized
+{
+	fn write(&self, buf: &MsgBuf) -> Result<(), SysErr>;
+	fn read(buf: &MsgBuf) -> Option<Self>;
+}
+
+/// The client side of a protocol, which calls a server over a channel
+#[derive(Debug)]
+pub struct RpcClient
+{
+	channel: usize,
+	buf: MsgBuf,
+}
+
+impl RpcClient
+{
+	pub fn new(channel: usize, buf: MsgBuf) -> Self
+	{
+		RpcClient {
+			channel,
+			buf,
+		}
+	}
+
+	/// sends `request` and waits for the response
+	///
+	/// returns InvlArgs if the request does not fit in the message buffer,
+	/// or the response is not valid
+	pub fn call<Req: RpcMsg, Resp: RpcMsg>(&mut self, request: &Req) -> Result<Resp, SysErr>
+	{
+		request.write(&self.buf)?;
+
+		match channel_call(self.channel, self.buf.cid()) {
+			SysErr::Ok => Resp::read(&self.buf).ok_or(SysErr::InvlArgs),
+			err => Err(err),
+		}
+	}
+}
+
+/// The server side of a protocol, which recieves calls on a channel
+#[derive(Debug)]
+pub struct RpcServer
+{
+	channel: usize,
+	buf: MsgBuf,
+	badge: usize,
+}
+
+impl RpcServer
+{
+	pub fn new(channel: usize, buf: MsgBuf) -> Self
+	{
+		RpcServer {
+			channel,
+			buf,
+			badge: 0,
+		}
+	}
+
+	/// badge of the channel capability the last recieved request was sent with, or 0 if it had no badge
+	pub fn badge(&self) -> usize
+	{
+		self.badge
+	}
+
+	/// recieves requests and replies with the response returned by `handler`,
+	/// until a channel operation fails
+	///
+	/// invalid requests are replied to with an empty message,
+	/// and requests that were not sent with a call are not replied to
+	pub fn serve<Req, Resp, F>(&mut self, mut handler: F) -> SysErr
+	where
+		Req: RpcMsg,
+		Resp: RpcMsg,
+		F: FnMut(Req) -> Resp,
+	{
+		let mut result = channel_recv(self.channel, self.buf.cid());
+
+		loop {
+			self.badge = match result {
+				Ok(badge) => badge,
+				Err(err) => return err,
+			};
+
+			let reply = self.buf.reply();
+			let response = Req::read(&self.buf).map(&mut handler);
+
+			if reply == 0 {
+				result = channel_recv(self.channel, self.buf.cid());
+				continue;
+			}
+
+			match response {
+				Some(response) => {
+					if response.write(&self.buf).is_err() {
+						self.buf.set_empty();
+					}
+				},
+				None => self.buf.set_empty(),
+			}
+
+			result = channel_reply_recv(reply, self.channel, self.buf.cid());
+		}
+	}
+}
+
+/// Defines a protocol, see the [module documentation](crate::os::aurora::rpc)
+#[macro_export]
+macro_rules! rpc {
+	(
+		$vis:vis protocol {
+			request $req:ident;
+			response $resp:ident;
+			client $client:ident;
+			server $server:ident;
+
+			$(
+				$variant:ident => fn $method:ident($($arg:ident: $arg_ty:ty),* $(,)?)
+					-> ($($ret:ident: $ret_ty:ty),* $(,)?);
+			)*
+		}
+	) => {
+		$crate::rpc!(@msg $vis $req { $($variant { $($arg: $arg_ty),* })* });
+		$crate::rpc!(@msg $vis $resp { $($variant { $($ret: $ret_ty),* })* });
+
+		$vis struct $client
+		{
+			rpc: $crate::os::aurora::rpc::RpcClient,
+		}
+
+		impl $client
+		{
+			pub fn new(rpc: $crate::os::aurora::rpc::RpcClient) -> Self
+			{
+				$client {
+					rpc,
+				}
+			}
+
+			$(
+				#[allow(unreachable_patterns)]
+				pub fn $method(&mut self, $($arg: $arg_ty),*)
+					-> Result<($($ret_ty),*), $crate::os::aurora::sys::SysErr>
+				{
+					let request = $req::$variant { $($arg),* };
+
+					match self.rpc.call::<$req, $resp>(&request)? {
+						$resp::$variant { $($ret),* } => Ok(($($ret),*)),
+						_ => Err($crate::os::aurora::sys::SysErr::InvlArgs),
+					}
+				}
+			)*
+		}
+
+		$vis trait $server
+		{
+			$(fn $method(&mut self, $($arg: $arg_ty),*) -> ($($ret_ty),*);)*
+
+			/// serves requests on `rpc` until a channel operation fails
+			fn serve(&mut self, rpc: &mut $crate::os::aurora::rpc::RpcServer)
+				-> $crate::os::aurora::sys::SysErr
+			where
+				Self: Sized,
+			{
+				rpc.serve(|request: $req| match request {
+					$(
+						$req::$variant { $($arg),* } => {
+							let ($($ret),*) = self.$method($($arg),*);
+							$resp::$variant { $($ret),* }
+						},
+					)*
+				})
+			}
+		}
+	};
+
+	(@msg $vis:vis $name:ident { $($variant:ident { $($field:ident: $ty:ty),* })* }) => {
+		$vis enum $name
+		{
+			$($variant { $($field: $ty),* },)*
+		}
+
+		impl $crate::os::aurora::rpc::RpcMsg for $name
+		{
+			fn write(
+				&self,
+				buf: &$crate::os::aurora::rpc::MsgBuf,
+			) -> Result<(), $crate::os::aurora::sys::SysErr>
+			{
+				use $crate::os::aurora::rpc::{MsgWriter, RpcArg};
+
+				// the first data word says which call the message is for
+				#[allow(dead_code)]
+				enum Index
+				{
+					$($variant,)*
+				}
+
+				match self {
+					$(
+						Self::$variant { $($field),* } => {
+							let mut msg = MsgWriter::new(buf, 0 $(+ <$ty as RpcArg>::CAPS)*);
+							msg.write_word(Index::$variant as usize);
+							$(msg.write($field);)*
+							msg.finish()
+						},
+					)*
+				}
+			}
+
+			fn read(buf: &$crate::os::aurora::rpc::MsgBuf) -> Option<Self>
+			{
+				use $crate::os::aurora::rpc::MsgReader;
+
+				#[allow(dead_code)]
+				enum Index
+				{
+					$($variant,)*
+				}
+
+				let mut msg = MsgReader::new(buf)?;
+				let index = msg.read_word()?;
+
+				$(
+					if index == Index::$variant as usize {
+						return Some(Self::$variant { $($field: msg.read()?),* });
+					}
+				)*
+
+				None
+			}
+		}
+	};
+}
+
+#[cfg(test)]
+#[allow(dead_code)]
+mod tests
+{
+	use super::*;
+
+	crate::rpc! {
+		protocol {
+			request TestRequest;
+			response TestResponse;
+			client TestClient;
+			server TestServer;
+
+			Open => fn open(dir: Cap, flags: usize) -> (file: Cap);
+			Read => fn read(file: Cap, offset: usize, signed: i32, last: bool) -> (mem: Cap, read: u16);
+		}
+	}
+
+	fn msg_buf(words: &mut [usize]) -> MsgBuf
+	{
+		unsafe { MsgBuf::from_raw(1, words.as_mut_ptr(), words.len()) }
+	}
+
+	#[test]
+	fn request_round_trip()
+	{
+		let mut words = [0; 16];
+		let buf = msg_buf(&mut words);
+
+		let request = TestRequest::Read {
+			file: Cap(7),
+			offset: 0x1000,
+			signed: -3,
+			last: true,
+		};
+		assert_eq!(request.write(&buf), Ok(()));
+
+		// 1 capability, then the call index and 3 arguments
+		assert_eq!(buf.get(LEN_WORD), Some(HEADER_LEN + 5));
+		assert_eq!(buf.get(FLAGS_WORD), Some(1 << CAP_COUNT_SHIFT));
+		assert_eq!(buf.get(HEADER_LEN), Some(7));
+
+		match TestRequest::read(&buf) {
+			Some(TestRequest::Read {
+				file,
+				offset,
+				signed,
+				last,
+			}) => {
+				assert_eq!(file, Cap(7));
+				assert_eq!(offset, 0x1000);
+				assert_eq!(signed, -3);
+				assert!(last);
+			},
+			_ => panic!("request was not decoded as a read"),
+		}
+	}
+
+	#[test]
+	fn response_round_trip()
+	{
+		let mut words = [0; 16];
+		let buf = msg_buf(&mut words);
+
+		let response = TestResponse::Read {
+			mem: Cap(3),
+			read: 512,
+		};
+		assert_eq!(response.write(&buf), Ok(()));
+
+		match TestResponse::read(&buf) {
+			Some(TestResponse::Read {
+				mem,
+				read,
+			}) => {
+				assert_eq!(mem, Cap(3));
+				assert_eq!(read, 512);
+			},
+			_ => panic!("response was not decoded as a read"),
+		}
+	}
+
+	#[test]
+	fn mapped_capabilities_are_skipped()
+	{
+		let mut words = [0; 16];
+		let buf = msg_buf(&mut words);
+
+		// the kernel puts the address of each mapped capability after the capabilities
+		buf.set(LEN_WORD, HEADER_LEN + 3);
+		buf.set(FLAGS_WORD, MSG_MAP | (1 << CAP_COUNT_SHIFT));
+		buf.set(HEADER_LEN, 9);
+		buf.set(HEADER_LEN + 1, 0x4000);
+		buf.set(HEADER_LEN + 2, 0);
+
+		match TestResponse::read(&buf) {
+			Some(TestResponse::Open {
+				file,
+			}) => assert_eq!(file, Cap(9)),
+			_ => panic!("response was not decoded as an open"),
+		}
+	}
+
+	#[test]
+	fn message_too_big()
+	{
+		let mut words = [0; HEADER_LEN + 3];
+		let buf = msg_buf(&mut words);
+
+		let request = TestRequest::Read {
+			file: Cap(7),
+			offset: 0,
+			signed: 0,
+			last: false,
+		};
+		assert_eq!(request.write(&buf), Err(SysErr::InvlArgs));
+	}
+
+	#[test]
+	fn invalid_index()
+	{
+		let mut words = [0; 16];
+		let buf = msg_buf(&mut words);
+
+		buf.set(LEN_WORD, HEADER_LEN + 1);
+		buf.set(FLAGS_WORD, 0);
+		buf.set(HEADER_LEN, 2);
+
+		assert!(TestRequest::read(&buf).is_none());
+	}
+}
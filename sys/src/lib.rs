@@ -2,9 +2,9 @@
 #![no_std]
 #![feature(asm)]
 
-use sys_consts::thread;
+use sys_consts::{kernel_nums, thread};
 pub use sys_consts::options::*;
-pub use sys_consts::SysErr;
+pub use sys_consts::{SysErr, SysNums};
 
 pub const PAGE_SIZE: usize = 4096;
 // filler for syscall macro to get right amount of return values
@@ -42,6 +42,7 @@ pub enum ThreadState
 	Destroy,
 	Sleep(usize),
 	Join(usize),
+	Await,
 }
 
 impl ThreadState
@@ -62,6 +63,7 @@ impl ThreadState
 				val = *num;
 				thread::JOIN
 			},
+			Self::Await => thread::AWAIT,
 		};
 
 		(reason, val)
@@ -365,3 +367,61 @@ macro_rules! syscall
 		(o1, o2, o3, o4, o5, o6, o7, o8, o9, o10)
 	}};
 }
+
+// the channel and registry syscalls are after the syscall macro so they can use it
+
+fn sys_err(n: usize) -> SysErr
+{
+	SysErr::new(n).unwrap_or(SysErr::Unknown)
+}
+
+// returns the badge of the recieved message
+pub fn channel_recv(channel: usize, msg_buf: usize) -> Result<usize, SysErr>
+{
+	let (err, badge) = unsafe { syscall!(kernel_nums::CHANNEL_RECV, 0, channel, msg_buf) };
+
+	match sys_err(err) {
+		SysErr::Ok => Ok(badge),
+		err => Err(err),
+	}
+}
+
+pub fn channel_call(channel: usize, msg_buf: usize) -> SysErr
+{
+	let (err, _) = unsafe { syscall!(kernel_nums::CHANNEL_CALL, 0, channel, msg_buf) };
+	sys_err(err)
+}
+
+// returns the badge of the recieved message
+pub fn channel_reply_recv(reply: usize, channel: usize, msg_buf: usize) -> Result<usize, SysErr>
+{
+	let (err, badge, _) = unsafe { syscall!(kernel_nums::CHANNEL_REPLY_RECV, 0, reply, channel, msg_buf) };
+
+	match sys_err(err) {
+		SysErr::Ok => Ok(badge),
+		err => Err(err),
+	}
+}
+
+pub fn reg(name: &str, channel: usize, options: RegOptions) -> SysErr
+{
+	let (err, _, _) =
+		unsafe { syscall!(kernel_nums::REG, options.bits(), name.as_ptr() as usize, name.len(), channel) };
+	sys_err(err)
+}
+
+pub fn connect(name: &str, pid: Option<usize>, options: ConnectOptions) -> Result<usize, SysErr>
+{
+	let (options, pid) = match pid {
+		Some(pid) => (options | ConnectOptions::PID, pid),
+		None => (options - ConnectOptions::PID, 0),
+	};
+
+	let (err, channel, _) =
+		unsafe { syscall!(kernel_nums::CONNECT, options.bits(), name.as_ptr() as usize, name.len(), pid) };
+
+	match sys_err(err) {
+		SysErr::Ok => Ok(channel),
+		err => Err(err),
+	}
+}
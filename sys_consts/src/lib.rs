@@ -70,6 +70,20 @@ pub mod thread
 	pub const DESTROY: usize = 1;
 	pub const SLEEP: usize = 2;
 	pub const JOIN: usize = 3;
+	pub const AWAIT: usize = 4;
+}
+
+/// Slots of syscalls in the kernel's syscall table, which is not ordered like [`SysNums`] yet
+// TODO: remove this once the kernel syscall table uses the same numbers as SysNums
+// these must match the order of syscalls in kernel/src/syscall/mod.rs
+pub mod kernel_nums
+{
+	// reg and connect are not in the spec, so they only have kernel slots
+	pub const REG: usize = 7;
+	pub const CHANNEL_RECV: usize = 33;
+	pub const CHANNEL_REPLY_RECV: usize = 38;
+	pub const CHANNEL_CALL: usize = 39;
+	pub const CONNECT: usize = 42;
 }
 
 /// Aurora kernel syscall numbers
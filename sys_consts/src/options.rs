@@ -1,6 +1,15 @@
 //! options for aurora kernel syscalls
 use bitflags::bitflags;
 
+bitflags! {
+	// options that can be used with every syscall
+	pub struct CommonOptions: u32
+	{
+		// destroy weak capabilities passed to the syscall that reference objects which are no longer alive
+		const WEAK_AUTO_DESTROY = 1 << 31;
+	}
+}
+
 bitflags! {
 	pub struct FutexOptions: u32
 	{
@@ -29,6 +38,41 @@ bitflags! {
 	}
 }
 
+bitflags! {
+	pub struct MapOptions: u32
+	{
+		// allows mapping memory at a specific address inside of reserved memory
+		const FORCE = 1;
+	}
+}
+
+bitflags! {
+	pub struct ChannelOptions: u32
+	{
+		// bits 0-3 are the capability flags of the new channel
+		const SC_RESIST = 1 << 4;
+	}
+}
+
+bitflags! {
+	pub struct EventOptions: u32
+	{
+		// event_new uses bits 0-3 as the capability flags of the new event
+		// only used for event_send
+		const SEND_ALL = 1;
+		// only used for event_listen
+		const TIMEOUT = 1 << 1;
+	}
+}
+
+bitflags! {
+	pub struct IpcOptions: u32
+	{
+		// send or recieve the message in registers instead of a message buffer
+		const REGS = 1;
+	}
+}
+
 bitflags! {
 	pub struct RegOptions: u32
 	{